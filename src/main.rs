@@ -5,18 +5,47 @@ use std::collections::{HashMap, HashSet};
 use std::fs::{File, create_dir_all, rename};
 use std::io::{self, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread::{self};
-use std::time::Instant;
+use std::time::{Instant, SystemTime};
 use walkdir::WalkDir;
 
-const QUICKCHECK_SIZE: usize = 8 * 1024 * 1024; // 8MB
+const QUICKCHECK_SIZE: usize = 8 * 1024 * 1024; // 8MB, default -C sample size
+/// --quick-adaptive: one sample per this many bytes of file size.
+const QUICKCHECK_ADAPTIVE_SPAN: u64 = 512 * 1024 * 1024;
+/// --quick-adaptive never takes more samples than this.
+const QUICKCHECK_MAX_SAMPLES: u64 = 64;
+/// Partial-hash stages run before the full hash: (label, sample size, samples).
+const PREFILTER_STAGES: [(&str, u64, u64); 2] = [
+    ("first 4 KB", 4 * 1024, 1),
+    ("1 MB sample", 256 * 1024, 4),
+];
 
 #[derive(Debug, Clone)]
 struct FileInfo {
     path: PathBuf,
     size: u64,
     folder_index: usize,
+    mtime: Option<SystemTime>, // recorded at scan time, re-checked before any move
+    archive_member: Option<ArchiveMember>, // --archives: virtual `archive!/member` entry
+    device: u64, // st_dev of the file (or its archive), for per-device I/O scheduling
+}
+
+/// Where a virtual FileInfo's content lives: member `name` of `archive`.
+/// Members can be hashed by streaming decompression but never moved.
+#[derive(Debug, Clone)]
+struct ArchiveMember {
+    archive: PathBuf,
+    kind: ArchiveKind,
+    name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
 }
 
 #[derive(Debug, Clone)]
@@ -39,24 +68,249 @@ struct CompareOptions {
     intra_folder: bool,
     // Fuzzy comparison options
     fuzzy: bool,           // -Z: enable fuzzy mode
-    tolerance: u64,        // -t N: max differing bytes allowed
+    tolerance: Option<u64>,     // -t N: max differing bytes allowed
+    tolerance_pct: Option<f64>, // -T P: max differing bytes as a percentage of the file size
+    exact_diff_count: bool,     // --exact-diff-count: read fuzzy pairs to the end even past the limit
+    similarity: Option<f64>,    // --similarity P: shift-tolerant fuzzy matching, min % of shared content
+    show_diff: bool,            // --show-diff: print where each fuzzy match differs
+    perceptual_image: bool,     // --perceptual image: match visually identical photos
+    perceptual_distance: u32,   // --perceptual-distance N: max Hamming distance of perceptual hashes
+    perceptual_audio: bool,     // --perceptual audio: match re-encoded music by audio fingerprint
+    audio_similarity: f64,      // --audio-similarity P: min % of matching fingerprint bits
+    text_normalize: bool,       // --text-normalize: match text files equal up to line endings/whitespace/BOM
+    archives: bool,             // --archives: also scan members of zip/tar/tar.gz files (report-only)
+    quick_sampling: QuickSampling, // -C: which parts of a file the quick check hashes
+    trust_quick: bool,          // --trust-quick: act on -C matches without a confirming full hash
+    prefilter: bool,            // partial-hash stages before the full hash (off with --no-prefilter)
+    device_threads: Option<usize>, // -A/-E: concurrent readers per storage device (None: by device type)
     fuzzy_as_dupes: bool,  // -U: treat fuzzy matches as exact duplicates
+    format_aware: bool,    // --format-aware: match files by payload, skipping known metadata (Fuzzy Mode 2)
+    on_collision: CollisionPolicy, // --on-collision: what to do if the target already exists
+    rehash_before_move: bool,      // --rehash: re-hash files right before moving them
+    dirs: bool,                    // --dirs: report (and move) whole duplicate directories
+    diff: bool,                    // --diff: list what differs between the folders instead of duplicates
+    merge: bool,                   // --merge: copy content missing from folder2 into it
+    renames: bool,                 // --renames: report files renamed/moved between folder1 and folder2
+    rename_script: Option<PathBuf>, // --rename-script FILE: write a script that applies folder1's layout to folder2
+    output_format: OutputFormat,   // --format: table or tsv
+}
+
+impl CompareOptions {
+    /// Largest byte difference a fuzzy pair of `size`-byte files may have.
+    /// When both -t and -T are given, the stricter of the two applies.
+    fn fuzzy_limit(&self, size: u64) -> u64 {
+        let by_pct = self.tolerance_pct.map(|pct| (size as f64 * pct / 100.0).floor() as u64);
+        match (self.tolerance, by_pct) {
+            (Some(bytes), Some(pct)) => bytes.min(pct),
+            (Some(bytes), None) => bytes,
+            (None, Some(pct)) => pct,
+            (None, None) => 0,
+        }
+    }
+
+    /// Whether any near-duplicate stage (run_fuzzy_stage) is enabled.
+    fn wants_fuzzy_stage(&self) -> bool {
+        self.fuzzy || self.format_aware || self.perceptual_image || self.perceptual_audio || self.text_normalize
+    }
+}
+
+/// Which byte ranges of a file the -C quick check hashes. Files whose samples
+/// would cover the whole file anyway are hashed in full.
+#[derive(Debug, Clone)]
+struct QuickSampling {
+    sample_size: u64,         // --quick-size
+    samples: u64,             // --quick-samples (2 = head and tail, the classic -C)
+    random_seed: Option<u64>, // --quick-offsets random --quick-seed S; None = evenly spaced
+    adaptive: bool,           // --quick-adaptive: more samples for bigger files
+}
+
+impl QuickSampling {
+    /// Start offsets of the samples for a `size`-byte file, increasing and
+    /// non-overlapping, or None if the file should be hashed in full.
+    /// Offsets depend only on the size and the settings, so same-size files
+    /// are always sampled at the same places.
+    fn offsets(&self, size: u64) -> Option<Vec<u64>> {
+        let mut count = self.samples.max(1);
+        if self.adaptive {
+            count = count.max((size / QUICKCHECK_ADAPTIVE_SPAN).min(QUICKCHECK_MAX_SAMPLES));
+        }
+        if size <= self.sample_size.saturating_mul(count) {
+            return None;
+        }
+
+        let offsets = match self.random_seed {
+            // Evenly spaced from the very start to the very end
+            None if count == 1 => vec![0],
+            None => (0..count)
+                .map(|i| i * (size - self.sample_size) / (count - 1))
+                .collect(),
+            // One random offset inside each of `count` equal slots, so samples
+            // never overlap and still cover the whole file
+            Some(seed) => {
+                let slot = size / count;
+                (0..count)
+                    .map(|i| {
+                        let start = i * slot;
+                        let end = if i == count - 1 { size } else { start + slot };
+                        let mut z = seed ^ size.rotate_left(17) ^ i.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+                        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+                        z ^= z >> 31;
+                        start + z % (end - self.sample_size - start + 1)
+                    })
+                    .collect()
+            }
+        };
+        Some(offsets)
+    }
+
+    /// QuickMatch, or Exact if the samples of a `size`-byte file cover all of it.
+    fn match_kind(&self, size: u64) -> MatchKind {
+        if self.offsets(size).is_some() { MatchKind::QuickMatch } else { MatchKind::Exact }
+    }
+}
+
+/// --quick-size default as given to clap, derived from QUICKCHECK_SIZE.
+fn quick_size_default() -> &'static str {
+    static DEFAULT: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+    DEFAULT.get_or_init(|| format!("{}M", QUICKCHECK_SIZE >> 20))
+}
+
+/// Parse a byte size such as `4096`, `512K`, `8M` or `1G` (binary units).
+fn parse_byte_size(text: &str) -> Result<u64, String> {
+    let upper = text.trim().to_ascii_uppercase();
+    let digits = upper.trim_end_matches(['B', 'I']);
+    let (number, multiplier) = match digits.chars().last() {
+        Some('K') => (&digits[..digits.len() - 1], 1u64 << 10),
+        Some('M') => (&digits[..digits.len() - 1], 1 << 20),
+        Some('G') => (&digits[..digits.len() - 1], 1 << 30),
+        _ => (digits, 1),
+    };
+    match number.trim().parse::<u64>() {
+        Ok(n) if n > 0 => n.checked_mul(multiplier).ok_or_else(|| format!("size too large: {}", text)),
+        _ => Err(format!("expected a positive size like 4096, 512K, 8M or 1G, got '{}'", text)),
+    }
+}
+
+/// What to do when a file is about to be moved into `deleted/` or `differ/`
+/// and something already sits at the target path (e.g. from an earlier run).
+/// None of the policies ever overwrite the existing file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CollisionPolicy {
+    Suffix, // keep both: append " (N)" to the new file's name
+    Skip,   // leave the source file where it is
+    Fail,   // abort the whole move stage with an error
 }
 
 /// How a duplicate group was matched.
 #[derive(Debug, Clone, PartialEq)]
 enum MatchKind {
     Exact,
-    Fuzzy { bytes_differing: u64 },
+    /// -C without a full hash: the sampled parts of the files are identical,
+    /// the rest was never read.
+    QuickMatch,
+    /// `reason` is set when the match comes from a format-aware comparison
+    /// (metadata skipped) rather than a byte count.
+    Fuzzy { bytes_differing: u64, reason: Option<String> },
+    /// --perceptual image: the pictures look the same; `distance` is the
+    /// largest Hamming distance between the perceptual hashes of any two
+    /// members (0–64, lower is closer).
+    Perceptual { distance: u32 },
+    /// --text-normalize: text files that are identical once line endings,
+    /// trailing whitespace, a UTF-8 BOM and trailing newlines are ignored.
+    TextNormalized,
 }
 
-#[derive(Debug)]
+impl MatchKind {
+    /// Short human-readable note on why a fuzzy pair matched.
+    fn fuzzy_note(&self) -> String {
+        match self {
+            MatchKind::Fuzzy { reason: Some(reason), .. } => reason.clone(),
+            MatchKind::Fuzzy { bytes_differing, .. } => format!("{} bytes differ", bytes_differing),
+            MatchKind::Perceptual { distance } => format!("perceptual distance {}", distance),
+            MatchKind::TextNormalized => "text identical after normalization".to_string(),
+            MatchKind::QuickMatch => "quick check only".to_string(),
+            MatchKind::Exact => "exact".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 struct DuplicateGroup {
     files_by_folder: Vec<Vec<PathBuf>>,
     size: u64,
     match_kind: MatchKind,
 }
 
+/// How the directories of a --dirs group relate to each other.
+#[derive(Debug, Clone, PartialEq)]
+enum DirMatchKind {
+    Identical,
+    /// Every file of the folder1 directory exists in the folder2 directory,
+    /// which has `extra_files` more.
+    Folder1Subset { extra_files: usize },
+    /// Every file of the folder2 directory exists in the folder1 directory,
+    /// which has `extra_files` more.
+    Folder2Subset { extra_files: usize },
+}
+
+/// A set of directories reported as one entry in --dirs mode.
+#[derive(Debug)]
+struct DirDuplicateGroup {
+    dirs_by_folder: Vec<Vec<PathBuf>>,
+    file_count: usize, // files in the (smaller) subtree
+    size: u64,         // total size of the (smaller) subtree
+    kind: DirMatchKind,
+}
+
+/// Classification of a file (or set of same-content files) in --diff mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum DiffStatus {
+    OnlyInFolder1,
+    OnlyInFolder2,
+    Moved,   // same content, different relative path
+    Changed, // same relative path, different content
+}
+
+#[derive(Debug)]
+struct DiffEntry {
+    status: DiffStatus,
+    folder1_paths: Vec<PathBuf>,
+    folder2_paths: Vec<PathBuf>,
+    size: u64,
+}
+
+/// How a same-content file was reorganized between folder1 and folder2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum RenameKind {
+    Renamed,         // same directory, different name
+    Moved,           // different directory, same name
+    MovedAndRenamed, // both
+}
+
+#[derive(Debug)]
+struct RenamePair {
+    kind: RenameKind,
+    folder1_path: PathBuf,
+    folder2_path: PathBuf,
+    size: u64,
+}
+
+/// Output format for report listings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Table,
+    Tsv, // tab-separated, one row per entry, for scripts
+}
+
+/// Per-directory data used to build the Merkle digests in --dirs mode.
+#[derive(Debug, Default)]
+struct DirNode {
+    folder_index: usize,
+    files: Vec<(String, u64, u64)>, // (file name, content id, size)
+    subdirs: Vec<PathBuf>,
+}
+
 fn main() -> io::Result<()> {
     let start_time = Instant::now();
     
@@ -92,7 +346,7 @@ fn main() -> io::Result<()> {
         )
         .arg(
             Arg::new("folder1")
-                .required(true)
+                .required_unless_present("hexdiff")
                 .help("First folder to compare (or the only folder in --single mode)")
                 .index(1),
         )
@@ -127,7 +381,57 @@ fn main() -> io::Result<()> {
             Arg::new("quick")
                 .short('C')
                 .long("quick")
-                .help("Quick content comparison (first and last 8MB)")
+                .help(format!("Quick content comparison: hash samples instead of whole files (by default the first and last {}B; see --quick-size, --quick-samples, --quick-offsets, --quick-adaptive)", quick_size_default()))
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("quick_size")
+                .long("quick-size")
+                .help(format!("With -C: size of each sample, e.g. 1M or 64K (default {})", quick_size_default()))
+                .value_name("SIZE")
+                .value_parser(parse_byte_size)
+                .default_value(quick_size_default()),
+        )
+        .arg(
+            Arg::new("quick_samples")
+                .long("quick-samples")
+                .help("With -C: number of samples per file (default 2: head and tail); more samples also catch files that differ only in the middle, such as disk images")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u64).range(1..=QUICKCHECK_MAX_SAMPLES))
+                .default_value("2"),
+        )
+        .arg(
+            Arg::new("quick_offsets")
+                .long("quick-offsets")
+                .help("With -C: where samples are taken: even (evenly spaced from start to end, default) or random (one seeded random offset per equal slice of the file)")
+                .value_name("MODE")
+                .value_parser(["even", "random"])
+                .default_value("even"),
+        )
+        .arg(
+            Arg::new("quick_seed")
+                .long("quick-seed")
+                .help("With --quick-offsets random: seed for the offsets (default 0); runs with the same seed sample the same places")
+                .value_name("SEED")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("quick_adaptive")
+                .long("quick-adaptive")
+                .help("With -C: take more samples for bigger files (one per 512MB, up to 64; never fewer than --quick-samples)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no_prefilter")
+                .long("no-prefilter")
+                .help("Full-hash every same-size file directly instead of first weeding out files by their first 4KB and a 1MB sample")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("trust_quick")
+                .long("trust-quick")
+                .help("With -C: move quick-check matches with -D/-F (and use them for --merge/--renames) without first confirming them by a full hash; files that differ only outside the samples may be treated as duplicates")
                 .action(ArgAction::SetTrue),
         )
         .arg(
@@ -172,6 +476,13 @@ fn main() -> io::Result<()> {
                 .help("Optimize for HDD usage (default)")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("device_threads")
+                .long("device-threads")
+                .help("With -A/-E: concurrent readers per storage device (default 1 on rotational disks with --hdd, which are read in physical order, and the CPU count otherwise). Files are scheduled per device, so a slow disk is never given more readers than this and does not hold up a fast one")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u64).range(1..)),
+        )
         .arg(
             Arg::new("hdd_deoptimized")
                 .short('M')
@@ -218,24 +529,156 @@ fn main() -> io::Result<()> {
             Arg::new("fuzzy")
                 .short('Z')
                 .long("fuzzy")
-                .help("Enable fuzzy comparison: files within --tolerance bytes are near-duplicates, moved to 'differ/' folder")
+                .help("Enable fuzzy comparison: files within --tolerance bytes (or --tolerance-pct) are near-duplicates, moved to 'differ/' folder")
                 .action(ArgAction::SetTrue),
         )
         .arg(
             Arg::new("tolerance")
                 .short('t')
                 .long("tolerance")
-                .help("Byte threshold for fuzzy comparison (required with -Z, always specify separately: -Z -t 50)")
+                .help("Byte threshold for fuzzy comparison (-Z needs -t and/or -T, always specify separately: -Z -t 50)")
                 .value_name("BYTES")
                 .value_parser(clap::value_parser!(u64)),
         )
+        .arg(
+            Arg::new("tolerance_pct")
+                .short('T')
+                .long("tolerance-pct")
+                .help("Threshold for fuzzy comparison as a percentage of the file size (e.g. -Z -T 0.01); with -t, both limits apply")
+                .value_name("PERCENT")
+                .value_parser(clap::value_parser!(f64)),
+        )
+        .arg(
+            Arg::new("similarity")
+                .long("similarity")
+                .help("Fuzzy: also match files of different sizes that share at least PERCENT of their content, found with content-defined chunking (tolerates inserted/removed bytes), e.g. -Z --similarity 95")
+                .value_name("PERCENT")
+                .value_parser(clap::value_parser!(f64)),
+        )
+        .arg(
+            Arg::new("perceptual")
+                .long("perceptual")
+                .help("Match files that look or sound the same even if re-encoded (comma-separated): 'image' compares perceptual hashes (dHash + pHash) of decoded JPEG, PNG, GIF, BMP, TIFF and WebP images, so resized, recompressed and converted copies match; 'audio' compares chroma fingerprints of decoded MP3, FLAC, AAC/M4A, ALAC, WAV, AIFF and Ogg Vorbis files, regardless of container, bitrate or tags")
+                .value_name("KIND")
+                .value_parser(["image", "audio"])
+                .value_delimiter(',')
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("audio_similarity")
+                .long("audio-similarity")
+                .help("With --perceptual audio: minimum percentage of matching fingerprint bits for two tracks to match (default 80; unrelated music scores around 50-60)")
+                .value_name("PERCENT")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("80"),
+        )
+        .arg(
+            Arg::new("perceptual_distance")
+                .long("perceptual-distance")
+                .help("With --perceptual: largest Hamming distance (0-64) between perceptual hashes that still counts as a match (default 8)")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u32).range(0..=64))
+                .default_value("8"),
+        )
+        .arg(
+            Arg::new("text_normalize")
+                .long("text-normalize")
+                .help("Match text files that differ only in line endings (CRLF/CR/LF), trailing whitespace, a UTF-8 BOM or the final newline; reported as TEXT-NORMALIZED")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("archives")
+                .long("archives")
+                .help("Also look inside .zip, .tar, .tar.gz/.tgz files: members are compared as `archive!/member` entries (hashed by streaming decompression). Groups with archive members are report-only")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("show_diff")
+                .long("show-diff")
+                .help("Fuzzy: print where each fuzzy match differs (byte ranges, hex/ASCII side by side, format labels such as EXIF tags) before anything is moved")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("hexdiff")
+                .long("hexdiff")
+                .help("Only show where two files differ (byte ranges, hex/ASCII side by side, format labels) and exit")
+                .value_names(["FILE_A", "FILE_B"])
+                .num_args(2)
+                .value_parser(clap::value_parser!(PathBuf))
+                .exclusive(true),
+        )
+        .arg(
+            Arg::new("exact_diff_count")
+                .long("exact-diff-count")
+                .help("Fuzzy: always read both files to the end to report the exact differing byte count (implied by -X); by default a pair is abandoned once it exceeds the tolerance")
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("fuzzy_as_dupes")
                 .short('U')
                 .long("fuzzy-as-dupes")
-                .help("Treat fuzzy matches as exact duplicates (requires -Z); eligible for -D/-F deletion")
+                .help("Treat fuzzy matches as exact duplicates (requires -Z or --format-aware); eligible for -D/-F deletion. Not allowed with --perceptual or --text-normalize, whose matches always go to differ/")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("format_aware")
+                .long("format-aware")
+                .help("Fuzzy Mode 2: match files whose payload is identical once known metadata is skipped (JPEG, PNG, MP4/MOV, FLAC, MP3, PDF), even if sizes differ")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("on_collision")
+                .long("on-collision")
+                .help("What to do when the target in 'deleted/' or 'differ/' already exists: suffix (default), skip or fail")
+                .value_name("POLICY")
+                .value_parser(["suffix", "skip", "fail"])
+                .default_value("suffix"),
+        )
+        .arg(
+            Arg::new("rehash")
+                .long("rehash")
+                .help("Re-hash each file and its match right before moving it (size and mtime are always re-checked)")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("dirs")
+                .long("dirs")
+                .help("Report identical directories (and directories contained in another) as single entries; -D/-F move whole directories")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("diff")
+                .long("diff")
+                .help("Folder diff: list files only in folder1, only in folder2, moved (same content, other path) and changed (same path, other content)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("merge")
+                .long("merge")
+                .help("Copy files whose content is missing from folder2 into folder2, keeping their relative path (folder1 is left untouched)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("renames")
+                .long("renames")
+                .help("Report files with identical content but a different relative path, as renamed, moved or moved+renamed")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("rename_script")
+                .long("rename-script")
+                .help("With --renames: write a script that renames folder2's files to match folder1's layout (.bat/.cmd for Windows, sh otherwise)")
+                .value_name("FILE")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .help("Output format for --diff and --renames results: table (default) or tsv")
+                .value_name("FORMAT")
+                .value_parser(["table", "tsv"])
+                .default_value("table"),
+        )
         .get_matches();
 
     // Handle version flags
@@ -256,6 +699,11 @@ fn main() -> io::Result<()> {
         return Ok(());
     }
 
+    if let Some(mut files) = matches.get_many::<PathBuf>("hexdiff") {
+        let (file_a, file_b) = (files.next().unwrap(), files.next().unwrap());
+        return show_file_diff(file_a, file_b);
+    }
+
     let single_mode = matches.get_flag("single");
     let folder1 = matches.get_one::<String>("folder1").unwrap();
     let folder2 = if single_mode {
@@ -267,18 +715,55 @@ fn main() -> io::Result<()> {
     // Validate fuzzy flags
     let fuzzy = matches.get_flag("fuzzy");
     let tolerance = matches.get_one::<u64>("tolerance").copied();
+    let tolerance_pct = matches.get_one::<f64>("tolerance_pct").copied();
+    let similarity = matches.get_one::<f64>("similarity").copied();
+    let perceptual_kinds: Vec<&str> = matches
+        .get_many::<String>("perceptual")
+        .map(|kinds| kinds.map(|k| k.as_str()).collect())
+        .unwrap_or_default();
     let fuzzy_as_dupes = matches.get_flag("fuzzy_as_dupes");
+    let format_aware = matches.get_flag("format_aware");
 
-    if fuzzy && tolerance.is_none() {
-        eprintln!("Error: -Z/--fuzzy requires -t/--tolerance to be specified.");
+    if fuzzy && tolerance.is_none() && tolerance_pct.is_none() && similarity.is_none() {
+        eprintln!("Error: -Z/--fuzzy requires -t/--tolerance, -T/--tolerance-pct or --similarity to be specified.");
         eprintln!("Example: duptool folder1 folder2 -Z -t 50");
         std::process::exit(1);
     }
-    if !fuzzy && tolerance.is_some() {
-        eprintln!("Warning: -t/--tolerance has no effect without -Z/--fuzzy.");
+    if let Some(pct) = tolerance_pct {
+        if !(0.0..=100.0).contains(&pct) {
+            eprintln!("Error: -T/--tolerance-pct must be between 0 and 100.");
+            std::process::exit(1);
+        }
+    }
+    if let Some(pct) = similarity {
+        if !(0.0 < pct && pct <= 100.0) {
+            eprintln!("Error: --similarity must be above 0 and at most 100.");
+            std::process::exit(1);
+        }
+        if pct < SIMILARITY_WARN_BELOW {
+            eprintln!(
+                "Warning: --similarity {} pairs files up to {:.0}× apart in size; below {}% nearly every file is compared with every other, which is slow on large trees.",
+                pct, (200.0 - pct) / pct, SIMILARITY_WARN_BELOW
+            );
+        }
+    }
+    let audio_similarity = *matches.get_one::<f64>("audio_similarity").unwrap();
+    if !(0.0..=100.0).contains(&audio_similarity) {
+        eprintln!("Error: --audio-similarity must be between 0 and 100.");
+        std::process::exit(1);
+    }
+    if !fuzzy && (tolerance.is_some() || tolerance_pct.is_some() || similarity.is_some()) {
+        eprintln!("Warning: -t/--tolerance, -T/--tolerance-pct and --similarity have no effect without -Z/--fuzzy.");
+    }
+    if fuzzy_as_dupes && !fuzzy && !format_aware {
+        eprintln!("Error: -U/--fuzzy-as-dupes requires -Z/--fuzzy or --format-aware.");
+        std::process::exit(1);
     }
-    if fuzzy_as_dupes && !fuzzy {
-        eprintln!("Error: -U/--fuzzy-as-dupes requires -Z/--fuzzy.");
+    // Perceptual and normalized-text matches are never byte-for-byte equal:
+    // they always go through differ/ for review, so -U must not make them
+    // deletable along with the -Z ones
+    if fuzzy_as_dupes && (!perceptual_kinds.is_empty() || matches.get_flag("text_normalize")) {
+        eprintln!("Error: -U/--fuzzy-as-dupes cannot be combined with --perceptual or --text-normalize; their matches always go to differ/ for review.");
         std::process::exit(1);
     }
 
@@ -299,8 +784,44 @@ fn main() -> io::Result<()> {
         force_delete: matches.get_flag("force_delete"),
         intra_folder: matches.get_flag("intra") || single_mode,
         fuzzy,
-        tolerance: tolerance.unwrap_or(0),
+        tolerance,
+        tolerance_pct,
+        exact_diff_count: matches.get_flag("exact_diff_count"),
+        similarity,
+        show_diff: matches.get_flag("show_diff"),
+        perceptual_image: perceptual_kinds.contains(&"image"),
+        perceptual_distance: *matches.get_one::<u32>("perceptual_distance").unwrap(),
+        perceptual_audio: perceptual_kinds.contains(&"audio"),
+        audio_similarity,
+        text_normalize: matches.get_flag("text_normalize"),
+        archives: matches.get_flag("archives"),
+        quick_sampling: QuickSampling {
+            sample_size: matches.get_one::<u64>("quick_size").copied().unwrap_or(QUICKCHECK_SIZE as u64),
+            samples: *matches.get_one::<u64>("quick_samples").unwrap(),
+            random_seed: (matches.get_one::<String>("quick_offsets").map(|s| s.as_str()) == Some("random"))
+                .then(|| *matches.get_one::<u64>("quick_seed").unwrap()),
+            adaptive: matches.get_flag("quick_adaptive"),
+        },
+        trust_quick: matches.get_flag("trust_quick"),
+        prefilter: !matches.get_flag("no_prefilter"),
+        device_threads: matches.get_one::<u64>("device_threads").map(|n| *n as usize),
         fuzzy_as_dupes,
+        format_aware,
+        on_collision: match matches.get_one::<String>("on_collision").map(|s| s.as_str()) {
+            Some("skip") => CollisionPolicy::Skip,
+            Some("fail") => CollisionPolicy::Fail,
+            _ => CollisionPolicy::Suffix,
+        },
+        rehash_before_move: matches.get_flag("rehash"),
+        dirs: matches.get_flag("dirs"),
+        diff: matches.get_flag("diff"),
+        merge: matches.get_flag("merge"),
+        renames: matches.get_flag("renames"),
+        rename_script: matches.get_one::<PathBuf>("rename_script").cloned(),
+        output_format: match matches.get_one::<String>("format").map(|s| s.as_str()) {
+            Some("tsv") => OutputFormat::Tsv,
+            _ => OutputFormat::Table,
+        },
     };
 
     if options.debug {
@@ -323,12 +844,38 @@ fn main() -> io::Result<()> {
         options.compare_size = true;
     }
 
+    if (options.diff || options.merge || options.renames) && single_mode {
+        eprintln!("Error: --diff/--merge/--renames need two folders.");
+        std::process::exit(1);
+    }
+    if options.rename_script.is_some() && !options.renames {
+        eprintln!("Error: --rename-script requires --renames.");
+        std::process::exit(1);
+    }
+    if options.diff && !options.merge && (options.delete_duplicates || options.force_delete) {
+        eprintln!("Warning: --diff is report-only; -D/-F are ignored.");
+    }
+    if options.merge && (options.delete_duplicates || options.force_delete) {
+        eprintln!("Error: --merge cannot be combined with -D/-F; run the deduplication first, then merge.");
+        std::process::exit(1);
+    }
+
+    if options.dirs && !options.compare_content {
+        eprintln!("Error: --dirs needs content comparison; name/size-only matches are not enough to call directories identical.");
+        std::process::exit(1);
+    }
+
+    if options.archives && (options.merge || options.renames || options.dirs) {
+        eprintln!("Error: --archives is report-only and cannot be combined with --merge, --renames or --dirs.");
+        std::process::exit(1);
+    }
+
     println!("Scanning directories...");
-    let folder1_files = collect_files(folder1, 0, options.hdd_optimized)?;
+    let folder1_files = collect_files(folder1, 0, options.hdd_optimized, options.archives)?;
     let folder2_files = if single_mode {
         Vec::new()
     } else {
-        collect_files(folder2, 1, options.hdd_optimized)?
+        collect_files(folder2, 1, options.hdd_optimized, options.archives)?
     };
 
     println!("Found {} files in {}", folder1_files.len(), folder1);
@@ -336,12 +883,124 @@ fn main() -> io::Result<()> {
         println!("Found {} files in {}", folder2_files.len(), folder2);
     }
 
-    let duplicates = find_duplicates(folder1_files, folder2_files, folder1, folder2, &options)?;
+    // Remember what every file looked like at scan time, so the move stage can
+    // refuse files that changed between hashing and acting on them.
+    let scan_index: HashMap<PathBuf, FileInfo> = folder1_files
+        .iter()
+        .chain(folder2_files.iter())
+        .map(|f| (f.path.clone(), f.clone()))
+        .collect();
+
+    if options.diff || options.merge || options.renames {
+        // Every cross-folder content match matters here, not just the ones
+        // an action could use: drop the filters that would hide some.
+        let mut diff_options = options.clone();
+        diff_options.compare_content = true;
+        diff_options.compare_name = false;
+        diff_options.delete_duplicates = false;
+        diff_options.force_delete = false;
+        diff_options.intra_folder = false;
+        diff_options.fuzzy = false;
+        diff_options.format_aware = false;
+        diff_options.perceptual_image = false;
+        diff_options.perceptual_audio = false;
+        diff_options.text_normalize = false;
+        let mut duplicates = find_duplicates(folder1_files, folder2_files, folder1, folder2, &diff_options)?;
+        if !options.trust_quick {
+            duplicates = confirm_quick_matches(duplicates, &scan_index, &options)?;
+        }
+        if options.diff {
+            let entries = find_folder_diff(&scan_index, &duplicates, folder1, folder2, options.trust_quick);
+            display_diff_results(&entries, folder1, folder2, options.output_format);
+        }
+        if options.renames {
+            let pairs = find_renames(&scan_index, &duplicates, folder1, folder2, options.trust_quick);
+            display_rename_results(&pairs, folder1, folder2, options.output_format);
+            if let Some(script) = &options.rename_script {
+                write_rename_script(&pairs, folder1, folder2, script)?;
+                println!("Rename script written to {}", script.display());
+            }
+        }
+        if options.merge {
+            merge_into_folder2(&scan_index, &duplicates, folder1, folder2, &options)?;
+        }
+        println!("Completed in {:.2} seconds", start_time.elapsed().as_secs_f32());
+        return Ok(());
+    }
+
+    let mut duplicates = find_duplicates(folder1_files, folder2_files, folder1, folder2, &options)?;
+
+    // -C alone never read whole files: confirm what is about to be moved,
+    // or reported as identical directories
+    if (options.delete_duplicates || options.force_delete || options.dirs) && !options.trust_quick {
+        duplicates = confirm_quick_matches(duplicates, &scan_index, &options)?;
+    }
+
+    // --dirs: collapse per-file groups that are fully explained by duplicate
+    // directories into one entry per directory set.
+    let (dir_groups, duplicates) = if options.dirs {
+        let dir_groups = find_duplicate_dirs(&scan_index, &duplicates, folder1, folder2, &options);
+        let moved_dirs = if options.delete_duplicates || options.force_delete {
+            move_dirs_to_deleted(&dir_groups, folder1, if single_mode { "" } else { folder2 }, &scan_index, &options)?
+        } else {
+            HashSet::new()
+        };
+        // Only identical directories, or ones actually moved (with their
+        // counterparts), explain their files; under a directory left in
+        // place, the per-file groups are still shown and acted on.
+        let covered_dirs: HashSet<&Path> = dir_groups
+            .iter()
+            .filter(|g| {
+                g.kind == DirMatchKind::Identical
+                    || g.dirs_by_folder.iter().flatten().any(|d| moved_dirs.contains(d))
+            })
+            .flat_map(|g| g.dirs_by_folder.iter().flatten().map(|d| d.as_path()))
+            .collect();
+        let remaining: Vec<DuplicateGroup> = duplicates
+            .into_iter()
+            .filter(|g| {
+                !g.files_by_folder.iter().flatten().all(|p| {
+                    p.ancestors().skip(1).any(|a| covered_dirs.contains(a))
+                })
+            })
+            .collect();
+        (dir_groups, remaining)
+    } else {
+        (Vec::new(), duplicates)
+    };
 
     // Separate exact and fuzzy match groups
     let (exact_groups, fuzzy_groups): (Vec<_>, Vec<_>) = duplicates
         .into_iter()
-        .partition(|g| g.match_kind == MatchKind::Exact);
+        .partition(|g| matches!(g.match_kind, MatchKind::Exact | MatchKind::QuickMatch));
+
+    // --archives: groups with an archive member are report-only
+    let is_actionable = |g: &DuplicateGroup| {
+        !g.files_by_folder
+            .iter()
+            .flatten()
+            .any(|p| scan_index.get(p).is_some_and(|f| f.archive_member.is_some()))
+    };
+    let report_only = exact_groups.iter().chain(fuzzy_groups.iter()).filter(|g| !is_actionable(g)).count();
+    if report_only > 0 && (options.delete_duplicates || options.force_delete) {
+        println!("{} group(s) include archive members and are report-only: nothing in them is moved", report_only);
+    }
+
+    // Show the differences while every file is still where it was found
+    if options.show_diff {
+        for group in &fuzzy_groups {
+            let mut members = group.files_by_folder.iter().flatten();
+            let pair = match (group.files_by_folder[0].first(), group.files_by_folder.get(1).and_then(|f| f.first())) {
+                (Some(a), Some(b)) => Some((a, b)),
+                _ => members.next().zip(members.next()),
+            };
+            if let Some((a, b)) = pair {
+                if let Err(e) = show_file_diff(a, b) {
+                    eprintln!("Error showing diff for {} <-> {}: {}", a.display(), b.display(), e);
+                }
+            }
+        }
+    }
 
     // Handle fuzzy groups: move to 'differ/' unless -U (treat as dupes)
     if !fuzzy_groups.is_empty() {
@@ -351,15 +1010,16 @@ fn main() -> io::Result<()> {
         } else {
             // Default fuzzy behaviour: move to 'differ/' folder (report-only if no -D/-F)
             if options.delete_duplicates || options.force_delete {
-                move_fuzzy_to_differ(&fuzzy_groups, folder1, folder2, &options)?;
+                let actionable: Vec<DuplicateGroup> = fuzzy_groups.iter().filter(|g| is_actionable(g)).cloned().collect();
+                move_fuzzy_to_differ(&actionable, folder1, folder2, &scan_index, &options)?;
             }
         }
     }
 
     // Combine for deletion: exact always eligible, fuzzy only if -U
-    let mut groups_for_deletion: Vec<&DuplicateGroup> = exact_groups.iter().collect();
+    let mut groups_for_deletion: Vec<&DuplicateGroup> = exact_groups.iter().filter(|g| is_actionable(g)).collect();
     if options.fuzzy_as_dupes {
-        groups_for_deletion.extend(fuzzy_groups.iter());
+        groups_for_deletion.extend(fuzzy_groups.iter().filter(|g| is_actionable(g)));
     }
 
     if (options.delete_duplicates || options.force_delete) && !groups_for_deletion.is_empty() {
@@ -370,7 +1030,7 @@ fn main() -> io::Result<()> {
                     size: g.size,
                     match_kind: g.match_kind.clone(),
                 }).collect::<Vec<_>>(),
-                folder1, folder2, &options
+                folder1, folder2, &scan_index, &options
             )?;
         } else {
             move_duplicates_to_deleted(
@@ -379,7 +1039,7 @@ fn main() -> io::Result<()> {
                     size: g.size,
                     match_kind: g.match_kind.clone(),
                 }).collect::<Vec<_>>(),
-                folder1, "", &options
+                folder1, "", &scan_index, &options
             )?;
         }
     }
@@ -389,6 +1049,9 @@ fn main() -> io::Result<()> {
         .chain(fuzzy_groups.into_iter())
         .collect();
 
+    if options.dirs {
+        display_dir_results(&dir_groups, folder1, if single_mode { "" } else { folder2 });
+    }
     display_results(&all_groups, folder1, if single_mode { "" } else { folder2 });
     
     println!("Completed in {:.2} seconds", start_time.elapsed().as_secs_f32());
@@ -396,7 +1059,7 @@ fn main() -> io::Result<()> {
     Ok(())
 }
 
-fn collect_files(root: &str, folder_index: usize, hdd_optimized: bool) -> io::Result<Vec<FileInfo>> {
+fn collect_files(root: &str, folder_index: usize, hdd_optimized: bool, archives: bool) -> io::Result<Vec<FileInfo>> {
     let mut files = Vec::new();
     
     let progress = ProgressBar::new_spinner();
@@ -409,17 +1072,41 @@ fn collect_files(root: &str, folder_index: usize, hdd_optimized: bool) -> io::Re
     for entry in WalkDir::new(root).into_iter().filter_map(|e| e.ok()) {
         if entry.file_type().is_file() {
             if let Ok(metadata) = entry.metadata() {
+                let device = device_id(&metadata);
                 files.push(FileInfo {
                     path: entry.path().to_path_buf(),
                     size: metadata.len(),
                     folder_index,
+                    mtime: metadata.modified().ok(),
+                    archive_member: None,
+                    device,
                 });
                 progress.inc(1);
+
+                if let Some(kind) = archives.then(|| archive_kind(entry.path())).flatten() {
+                    match list_archive_members(entry.path(), kind) {
+                        Ok(members) => {
+                            for (name, size) in members {
+                                files.push(FileInfo {
+                                    path: PathBuf::from(format!("{}!/{}", entry.path().display(), name)),
+                                    size,
+                                    folder_index,
+                                    mtime: None,
+                                    archive_member: Some(ArchiveMember { archive: entry.path().to_path_buf(), kind, name }),
+                                    device,
+                                });
+                                progress.inc(1);
+                            }
+                        }
+                        Err(e) => eprintln!("Skipping unreadable archive {}: {}", entry.path().display(), e),
+                    }
+                }
             }
         }
     }
 
-    // Sort files by path to improve sequential access on HDDs if hdd_optimized is true
+    // Sort files by path for stable output on HDDs if hdd_optimized is true;
+    // hashing is later ordered by physical position (see sort_by_disk_order)
     if hdd_optimized {
         files.sort_by(|a, b| a.path.cmp(&b.path));
     }
@@ -428,6 +1115,117 @@ fn collect_files(root: &str, folder_index: usize, hdd_optimized: bool) -> io::Re
     Ok(files)
 }
 
+/// The device a file lives on (st_dev); 0 where the platform has no such id,
+/// which puts every file on one device.
+#[cfg(unix)]
+fn device_id(metadata: &std::fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.dev()
+}
+
+#[cfg(not(unix))]
+fn device_id(_metadata: &std::fs::Metadata) -> u64 {
+    0
+}
+
+/// --archives: the archive format of `path`, judged by its extension.
+fn archive_kind(path: &Path) -> Option<ArchiveKind> {
+    let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+    if name.ends_with(".zip") {
+        Some(ArchiveKind::Zip)
+    } else if name.ends_with(".tar") {
+        Some(ArchiveKind::Tar)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(ArchiveKind::TarGz)
+    } else {
+        None
+    }
+}
+
+/// Names and uncompressed sizes of the regular files inside an archive.
+/// Nested archives are not opened.
+fn list_archive_members(path: &Path, kind: ArchiveKind) -> io::Result<Vec<(String, u64)>> {
+    let mut members = Vec::new();
+    match kind {
+        ArchiveKind::Zip => {
+            let mut archive = zip::ZipArchive::new(File::open(path)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            for i in 0..archive.len() {
+                let entry = archive.by_index_raw(i).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                if entry.is_file() {
+                    members.push((entry.name().to_string(), entry.size()));
+                }
+            }
+        }
+        ArchiveKind::Tar | ArchiveKind::TarGz => {
+            let mut archive = tar_reader(path, kind)?;
+            for entry in archive.entries()? {
+                let entry = entry?;
+                if entry.header().entry_type().is_file() {
+                    members.push((entry.path()?.to_string_lossy().into_owned(), entry.size()));
+                }
+            }
+        }
+    }
+    Ok(members)
+}
+
+fn tar_reader(path: &Path, kind: ArchiveKind) -> io::Result<tar::Archive<Box<dyn Read>>> {
+    let file = io::BufReader::with_capacity(1024 * 1024, File::open(path)?);
+    let reader: Box<dyn Read> = if kind == ArchiveKind::TarGz {
+        Box::new(flate2::read::GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    Ok(tar::Archive::new(reader))
+}
+
+/// Hash an archive member by streaming decompression, producing the same
+/// hash calculate_file_hash would give the member's bytes on disk (including
+/// the -C sampling, done by skipping forward between samples). Tar members
+/// are found by reading the archive from the start, so every hashed member
+/// of a tarball costs one pass up to that member.
+fn hash_archive_member(member: &ArchiveMember, size: u64, quick: Option<&QuickSampling>) -> io::Result<String> {
+    let hash = |reader: &mut dyn Read| -> io::Result<String> {
+        let mut hasher = Context::new();
+        match quick.and_then(|q| Some((q.offsets(size)?, q.sample_size))) {
+            Some((offsets, sample_size)) => {
+                let mut pos = 0u64;
+                for offset in offsets {
+                    io::copy(&mut (&mut *reader).take(offset - pos), &mut io::sink())?;
+                    io::copy(&mut (&mut *reader).take(sample_size), &mut hasher)?;
+                    pos = offset + sample_size;
+                }
+            }
+            None => {
+                io::copy(reader, &mut hasher)?;
+            }
+        }
+        Ok(format!("{:x}", hasher.compute()))
+    };
+
+    match member.kind {
+        ArchiveKind::Zip => {
+            let mut archive = zip::ZipArchive::new(File::open(&member.archive)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let mut entry = archive
+                .by_name(&member.name)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            hash(&mut entry)
+        }
+        ArchiveKind::Tar | ArchiveKind::TarGz => {
+            let mut archive = tar_reader(&member.archive, member.kind)?;
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                if entry.path()?.to_string_lossy() == member.name {
+                    return hash(&mut entry);
+                }
+            }
+            Err(io::Error::new(io::ErrorKind::NotFound, format!("{} not found in archive", member.name)))
+        }
+    }
+}
+
 fn find_duplicates(
     folder1_files: Vec<FileInfo>, 
     folder2_files: Vec<FileInfo>, 
@@ -439,10 +1237,8 @@ fn find_duplicates(
     
     // Group files by size as a first pass
     let mut size_groups: HashMap<u64, Vec<FileInfo>> = HashMap::new();
-    for file in folder1_files.clone() {
-        size_groups.entry(file.size).or_default().push(file);
-    }
-    for file in folder2_files.clone() {
+    let all_files: Vec<FileInfo> = folder1_files.into_iter().chain(folder2_files).collect();
+    for file in all_files.iter().cloned() {
         size_groups.entry(file.size).or_default().push(file);
     }
 
@@ -516,7 +1312,8 @@ fn find_duplicates(
     };
 
     // --- CORRECT QUICK CHECK LOGIC ---
-    // - If only -C: compare only first and last 8MB, never full hash.
+    // - If only -C: compare only the quick-check samples, never full hash;
+    //   groups are reported as QuickMatch and confirmed in main before -D/-F.
     // - If -C and -A/-E: first filter by quick check, then do full hash for those that match.
     // - If -A/-E without -C: always do full hash.
     // - If neither: always do full hash (sync).
@@ -553,7 +1350,7 @@ fn find_duplicates(
                 progress_bar.inc(1);
                 let mut quick_hash_map: HashMap<String, Vec<FileInfo>> = HashMap::new();
                 for file in &group {
-                    if let Some(h) = calculate_file_hash(file, true).ok().flatten() {
+                    if let Some(h) = calculate_file_hash(file, Some(&options.quick_sampling)).ok().flatten() {
                         quick_hash_map.entry(h).or_default().push(file.clone());
                     }
                     processed_files += 1;
@@ -561,7 +1358,7 @@ fn find_duplicates(
                 }
                 quick_hash_map
                     .into_values()
-                    .filter(|files| files.len() > 1 && has_files_from_both_folders(files))
+                    .filter(|files| files.len() > 1 && (options.intra_folder || has_files_from_both_folders(files)))
                     .collect::<Vec<Vec<FileInfo>>>()
             })
             .collect();
@@ -578,34 +1375,15 @@ fn find_duplicates(
                 for file in group {
                     files_by_folder[file.folder_index].push(file.path.clone());
                 }
-                duplicates.push(DuplicateGroup { files_by_folder, size, match_kind: MatchKind::Exact });
+                duplicates.push(DuplicateGroup { files_by_folder, size, match_kind: options.quick_sampling.match_kind(size) });
             }
         }
 
-        // Fuzzy stage: -C quick-matched groups are exact; the original
+        // Fuzzy stage: -C quick-matched groups count as matched; the original
         // name_filtered_groups that *didn't* pass quick check are the fuzzy
         // candidates. We reconstruct them from all size-groups minus exact hits.
-        if options.fuzzy {
-            let exact_paths: HashSet<PathBuf> = duplicates
-                .iter()
-                .flat_map(|g| g.files_by_folder.iter().flat_map(|v| v.iter().cloned()))
-                .collect();
-            let fuzzy_candidates: Vec<Vec<FileInfo>> = quick_checked_groups
-                .iter()
-                .map(|group| {
-                    group.iter()
-                        .filter(|f| !exact_paths.contains(&f.path))
-                        .cloned()
-                        .collect::<Vec<_>>()
-                })
-                .filter(|g| g.len() > 1 && has_files_from_both_folders(g))
-                .collect();
-            if !fuzzy_candidates.is_empty() {
-                println!("Running fuzzy comparison on {} candidate groups...", fuzzy_candidates.len());
-                let fuzzy_results = fuzzy_compare_groups(&fuzzy_candidates, options)?;
-                println!("Fuzzy comparison found {} near-duplicate groups", fuzzy_results.len());
-                duplicates.extend(fuzzy_results);
-            }
+        if options.wants_fuzzy_stage() {
+            run_fuzzy_stage(&mut duplicates, &quick_checked_groups, &all_files, options)?;
         }
 
         return Ok(duplicates);
@@ -619,25 +1397,34 @@ fn find_duplicates(
             .flat_map(|group| {
                 let mut quick_hash_map: HashMap<String, Vec<FileInfo>> = HashMap::new();
                 for file in &group {
-                    if let Some(h) = calculate_file_hash(file, true).ok().flatten() {
+                    if let Some(h) = calculate_file_hash(file, Some(&options.quick_sampling)).ok().flatten() {
                         quick_hash_map.entry(h).or_default().push(file.clone());
                     }
                 }
                 quick_hash_map
                     .into_values()
-                    .filter(|files| files.len() > 1 && has_files_from_both_folders(files))
+                    .filter(|files| files.len() > 1 && (options.intra_folder || has_files_from_both_folders(files)))
                     .collect::<Vec<Vec<FileInfo>>>()
             })
             .collect();
         println!("After quick check: {} groups remain", quick_checked_groups.len());
     }
 
+    // --- STAGED PREFILTER ---
+    // Without -C, weed out files by cheap partial hashes first so the full
+    // hash only reads files that still have a partner. The fuzzy stage keeps
+    // using quick_checked_groups.
+    let mut full_hash_groups = quick_checked_groups.clone();
+    if options.compare_content && !options.quick_content_check && options.prefilter {
+        full_hash_groups = staged_prefilter(full_hash_groups, options)?;
+    }
+
     // --- FULL HASH FOR ALL FILES IN GROUPS ---
     let mut duplicates = Vec::new();
 
     if options.compare_content {
-        let total_groups = quick_checked_groups.len();
-        let total_files: u64 = quick_checked_groups.iter().map(|g| g.len() as u64).sum();
+        let total_groups = full_hash_groups.len();
+        let total_files: u64 = full_hash_groups.iter().map(|g| g.len() as u64).sum();
 
         // --- Use MultiProgress for two progress bars ---
         let m = MultiProgress::new();
@@ -654,9 +1441,10 @@ fn find_duplicates(
             .template("[{elapsed_precise}] {bar:40.green/white} {pos}/{len} files ({eta})")
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?);
 
-        // Clone before moving into closure so the fuzzy stage can still use
-        // quick_checked_groups afterwards.
-        let groups_for_thread = quick_checked_groups.clone();
+        // Move the prefiltered groups into the closure; the fuzzy stage still
+        // uses quick_checked_groups afterwards.
+        let groups_for_thread = full_hash_groups;
+        let pair_stats = Arc::new(Mutex::new(PairCompareStats::default()));
 
         // Run the comparison in a closure so we can join the progress bars after
         let result = if options.async_compare || options.enhanced_async {
@@ -664,9 +1452,10 @@ fn find_duplicates(
             full_hash_options.quick_content_check = false;
             let progress_bar = progress_bar.clone();
             let file_progress = file_progress.clone();
+            let pair_stats = Arc::clone(&pair_stats);
             std::thread::spawn(move || {
                 async_content_compare_with_file_progress(
-                    groups_for_thread, full_hash_options, progress_bar, file_progress
+                    groups_for_thread, full_hash_options, progress_bar, file_progress, pair_stats
                 )
             })
         } else {
@@ -674,9 +1463,10 @@ fn find_duplicates(
             full_hash_options.quick_content_check = false;
             let progress_bar = progress_bar.clone();
             let file_progress = file_progress.clone();
+            let pair_stats = Arc::clone(&pair_stats);
             std::thread::spawn(move || {
                 sync_content_compare_with_file_progress(
-                    groups_for_thread, &full_hash_options, progress_bar, file_progress
+                    groups_for_thread, &full_hash_options, progress_bar, file_progress, pair_stats
                 )
             })
         };
@@ -685,6 +1475,13 @@ fn find_duplicates(
         // Fallback for indicatif versions without join_and_clear/join:
         duplicates = result.join().unwrap()?;
         m.clear().unwrap();
+        let pair_stats = pair_stats.lock().unwrap();
+        if pair_stats.pairs > 0 {
+            println!(
+                "Direct comparison: {} file pair(s), {} differed, {} not read",
+                pair_stats.pairs, pair_stats.differed, format_size(pair_stats.bytes_skipped)
+            );
+        }
     } else if options.quick_content_check && (options.async_compare || options.enhanced_async) && !options.compare_content {
         // Show a progress bar for the async quick check (when -C and -A/-E, but not --content)
         let total_groups = quick_checked_groups.len();
@@ -714,34 +1511,15 @@ fn find_duplicates(
                 for file in group {
                     files_by_folder[file.folder_index].push(file.path.clone());
                 }
-                final_duplicates.push(DuplicateGroup { files_by_folder, size, match_kind: MatchKind::Exact });
+                final_duplicates.push(DuplicateGroup { files_by_folder, size, match_kind: options.quick_sampling.match_kind(size) });
             }
         }
         group_bar.finish();
         file_bar.finish();
         m.clear().unwrap();
         // Fuzzy on remaining candidates before the early return
-        if options.fuzzy {
-            let exact_paths: HashSet<PathBuf> = final_duplicates
-                .iter()
-                .flat_map(|g| g.files_by_folder.iter().flat_map(|v| v.iter().cloned()))
-                .collect();
-            let fuzzy_candidates: Vec<Vec<FileInfo>> = quick_checked_groups
-                .iter()
-                .map(|group| {
-                    group.iter()
-                        .filter(|f| !exact_paths.contains(&f.path))
-                        .cloned()
-                        .collect::<Vec<_>>()
-                })
-                .filter(|g| g.len() > 1 && has_files_from_both_folders(g))
-                .collect();
-            if !fuzzy_candidates.is_empty() {
-                println!("Running fuzzy comparison on {} candidate groups...", fuzzy_candidates.len());
-                let fuzzy_results = fuzzy_compare_groups(&fuzzy_candidates, options)?;
-                println!("Fuzzy comparison found {} near-duplicate groups", fuzzy_results.len());
-                final_duplicates.extend(fuzzy_results);
-            }
+        if options.wants_fuzzy_stage() {
+            run_fuzzy_stage(&mut final_duplicates, &quick_checked_groups, &all_files, options)?;
         }
         return Ok(final_duplicates);
     } else {
@@ -765,34 +1543,9 @@ fn find_duplicates(
     // exact candidates for fuzzy — they've already been filtered to same-size
     // cross-folder pairs, so we just re-use quick_checked_groups that weren't
     // promoted to exact duplicates.
-    if options.fuzzy {
-        // Collect the paths that are already covered by exact matches so we
-        // don't double-report them.
-        let exact_paths: HashSet<PathBuf> = duplicates
-            .iter()
-            .flat_map(|g| g.files_by_folder.iter().flat_map(|v| v.iter().cloned()))
-            .collect();
-
-        // Build candidate groups: same-size, cross-folder, not already exact.
-        let fuzzy_candidates: Vec<Vec<FileInfo>> = quick_checked_groups
-            .iter()
-            .map(|group| {
-                group
-                    .iter()
-                    .filter(|f| !exact_paths.contains(&f.path))
-                    .cloned()
-                    .collect::<Vec<_>>()
-            })
-            .filter(|g| g.len() > 1 && has_files_from_both_folders(g))
-            .collect();
-
-        if !fuzzy_candidates.is_empty() {
-            println!("Running fuzzy comparison on {} candidate groups...", fuzzy_candidates.len());
-            let fuzzy_results = fuzzy_compare_groups(&fuzzy_candidates, options)?;
-            println!("Fuzzy comparison found {} near-duplicate groups", fuzzy_results.len());
-            duplicates.extend(fuzzy_results);
-        }
-    }
+    if options.wants_fuzzy_stage() {
+        run_fuzzy_stage(&mut duplicates, &quick_checked_groups, &all_files, options)?;
+    }
 
     // Sort results by the path of the first file in each group for deterministic output.
     duplicates.sort_by(|a, b| {
@@ -804,6 +1557,399 @@ fn find_duplicates(
     Ok(duplicates)
 }
 
+/// Full-hash every -C quick-check group, keeping as exact groups only the
+/// files whose whole content really matches. Other groups pass unchanged.
+fn confirm_quick_matches(
+    duplicates: Vec<DuplicateGroup>,
+    scan_index: &HashMap<PathBuf, FileInfo>,
+    options: &CompareOptions,
+) -> io::Result<Vec<DuplicateGroup>> {
+    let (quick, mut confirmed): (Vec<_>, Vec<_>) = duplicates
+        .into_iter()
+        .partition(|g| g.match_kind == MatchKind::QuickMatch);
+    if quick.is_empty() {
+        return Ok(confirmed);
+    }
+
+    let total_files: u64 = quick.iter().map(|g| g.files_by_folder.iter().flatten().count() as u64).sum();
+    println!("Confirming {} quick-check group(s) with a full hash before acting on them...", quick.len());
+    let progress = ProgressBar::new(total_files);
+    progress.set_style(ProgressStyle::default_bar()
+        .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} files ({eta})")
+        .map_err(io::Error::other)?);
+
+    let quick_count = quick.len();
+    let mut rejected = 0;
+    for group in quick {
+        let mut content_groups: HashMap<String, Vec<FileInfo>> = HashMap::new();
+        for path in group.files_by_folder.iter().flatten() {
+            // A file that can no longer be read just drops out of its group
+            match calculate_file_hash(&scan_index[path], None) {
+                Ok(Some(hash)) => content_groups.entry(hash).or_default().push(scan_index[path].clone()),
+                Ok(None) => {}
+                Err(e) => eprintln!("Could not confirm {}: {}", path.display(), e),
+            }
+            progress.inc(1);
+        }
+        let before = confirmed.len();
+        for (_, files) in content_groups {
+            if files.len() > 1 && (options.intra_folder || has_files_from_both_folders(&files)) {
+                let mut files_by_folder = vec![Vec::new(), Vec::new()];
+                for file in files {
+                    files_by_folder[file.folder_index].push(file.path);
+                }
+                confirmed.push(DuplicateGroup { files_by_folder, size: group.size, match_kind: MatchKind::Exact });
+            }
+        }
+        if confirmed.len() == before {
+            rejected += 1;
+        }
+    }
+    progress.finish_and_clear();
+    println!("Quick check confirmed for {} group(s); {} turned out to differ", quick_count - rejected, rejected);
+
+    confirmed.sort_by(|a, b| a.files_by_folder.iter().flatten().next().cmp(&b.files_by_folder.iter().flatten().next()));
+    Ok(confirmed)
+}
+
+/// Run the PREFILTER_STAGES over same-size groups: split each group by a
+/// partial hash and drop files left without a partner. A stage is skipped
+/// for files it would read in full anyway. Prints per-stage statistics.
+fn staged_prefilter(groups: Vec<Vec<FileInfo>>, options: &CompareOptions) -> io::Result<Vec<Vec<FileInfo>>> {
+    let files_before: usize = groups.iter().map(|g| g.len()).sum();
+    let mut groups = groups;
+    let mut stage_samplings: Vec<QuickSampling> = Vec::new();
+
+    for (label, sample_size, samples) in PREFILTER_STAGES {
+        let sampling = QuickSampling { sample_size, samples, random_seed: None, adaptive: false };
+        let (to_split, mut kept): (Vec<_>, Vec<_>) = groups
+            .into_iter()
+            .partition(|g| g.first().is_some_and(|f| sampling.offsets(f.size).is_some()));
+        let files_in: usize = to_split.iter().map(|g| g.len()).sum();
+
+        let progress = ProgressBar::new(files_in as u64);
+        progress.set_style(ProgressStyle::default_bar()
+            .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} files ({msg})")
+            .map_err(io::Error::other)?);
+        progress.set_message(label);
+
+        let split = prefilter_split(&to_split, &sampling, options, &progress)?;
+        progress.finish_and_clear();
+
+        // Bytes an eliminated file would still have needed for its full hash
+        let survivors: HashSet<&Path> = split.iter().flatten().map(|f| f.path.as_path()).collect();
+        let mut eliminated = 0;
+        let mut bytes_saved = 0u64;
+        for file in to_split.iter().flatten().filter(|f| !survivors.contains(f.path.as_path())) {
+            let read: u64 = stage_samplings
+                .iter()
+                .chain(std::iter::once(&sampling))
+                .filter(|s| s.offsets(file.size).is_some())
+                .map(|s| s.sample_size * s.samples)
+                .sum();
+            eliminated += 1;
+            bytes_saved += file.size.saturating_sub(read);
+        }
+        println!(
+            "Prefilter ({}): {} files checked, {} eliminated, {} of full reads saved",
+            label, files_in, eliminated, format_size(bytes_saved)
+        );
+
+        kept.extend(split);
+        groups = kept;
+        stage_samplings.push(sampling);
+    }
+
+    let files_after: usize = groups.iter().map(|g| g.len()).sum();
+    println!("After prefilter: {} of {} files left for the full hash", files_after, files_before);
+    Ok(groups)
+}
+
+/// One prefilter stage over some groups: split each by the `sampling` hash,
+/// keeping only subgroups that can still hold a duplicate. With -A/-E the
+/// files are hashed per device (see run_per_device).
+fn prefilter_split(
+    groups: &[Vec<FileInfo>],
+    sampling: &QuickSampling,
+    options: &CompareOptions,
+    progress: &ProgressBar,
+) -> io::Result<Vec<Vec<FileInfo>>> {
+    let hash_file = |(group_index, file): (usize, FileInfo)| -> io::Result<(usize, FileInfo, Option<String>)> {
+        let hash = calculate_file_hash(&file, Some(sampling))?;
+        progress.inc(1);
+        Ok((group_index, file, hash))
+    };
+    let mut jobs: Vec<(u64, Option<u64>, (usize, FileInfo))> = groups
+        .iter()
+        .enumerate()
+        .flat_map(|(i, group)| group.iter().map(move |f| (f.device, None, (i, f.clone()))))
+        .collect();
+    if options.hdd_optimized {
+        sort_by_disk_order(&mut jobs, |(_, _, (_, f))| f, options.debug);
+    }
+    let hashed = if options.async_compare || options.enhanced_async {
+        run_per_device(jobs, |device| readers_per_device(device, options), options.debug, hash_file)?
+    } else {
+        jobs.into_iter().map(|(_, _, job)| hash_file(job)).collect::<io::Result<Vec<_>>>()?
+    };
+
+    let mut partial_groups: Vec<HashMap<String, Vec<FileInfo>>> = vec![HashMap::new(); groups.len()];
+    for (group_index, file, hash) in hashed {
+        if let Some(hash) = hash {
+            partial_groups[group_index].entry(hash).or_default().push(file);
+        }
+    }
+    Ok(partial_groups
+        .into_iter()
+        .flat_map(|g| g.into_values())
+        .filter(|files| files.len() > 1 && (options.intra_folder || has_files_from_both_folders(files)))
+        .collect())
+}
+
+/// --hdd: sort hashing work so each disk is read in physical order rather
+/// than path order, cutting head seeks. `file` picks the file to go by.
+/// Files on non-rotational devices keep their order and are not opened.
+/// Every key is computed once: each one opens the file.
+fn sort_by_disk_order<T>(items: &mut Vec<T>, file: impl Fn(&T) -> &FileInfo, debug: bool) {
+    let mut keyed: Vec<((u8, u64), T)> = items
+        .drain(..)
+        .map(|item| {
+            let f = file(&item);
+            let key = if is_rotational(f.device) { disk_order_key(f) } else { (3, 0) };
+            (key, item)
+        })
+        .collect();
+    keyed.sort_by_key(|(key, _)| *key);
+    if debug {
+        let mut by_source = [0usize; 4];
+        for ((source, _), _) in &keyed {
+            by_source[*source as usize] += 1;
+        }
+        println!(
+            "Disk order for {} item(s): {} by physical extent, {} by inode, {} unordered, {} on non-rotational devices",
+            keyed.len(), by_source[0], by_source[1], by_source[2], by_source[3]
+        );
+    }
+    items.extend(keyed.into_iter().map(|(_, item)| item));
+}
+
+/// Readers per device for run_per_device: --device-threads if given, else 1
+/// on a rotational disk with --hdd (more would undo the disk order), else
+/// the CPU count.
+fn readers_per_device(device: u64, options: &CompareOptions) -> usize {
+    match options.device_threads {
+        Some(n) => n,
+        None if options.hdd_optimized && is_rotational(device) => 1,
+        None => num_cpus::get(),
+    }
+}
+
+/// Whether `device` (st_dev) is a spinning disk, from
+/// /sys/dev/block/MAJ:MIN/queue/rotational (or the parent disk's, for a
+/// partition). Unknown devices count as rotational. Cached per device.
+fn is_rotational(device: u64) -> bool {
+    static CACHE: std::sync::OnceLock<Mutex<HashMap<u64, bool>>> = std::sync::OnceLock::new();
+    let cache = CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(&rotational) = cache.lock().unwrap().get(&device) {
+        return rotational;
+    }
+    let rotational = device_rotational_flag(device).unwrap_or(true);
+    cache.lock().unwrap().insert(device, rotational);
+    rotational
+}
+
+#[cfg(target_os = "linux")]
+fn device_rotational_flag(device: u64) -> Option<bool> {
+    // glibc's dev_t layout
+    let major = ((device >> 32) & 0xffff_f000) | ((device >> 8) & 0xfff);
+    let minor = ((device >> 12) & 0xffff_ff00) | (device & 0xff);
+    let base = format!("/sys/dev/block/{}:{}", major, minor);
+    ["queue/rotational", "../queue/rotational"]
+        .iter()
+        .find_map(|rel| std::fs::read_to_string(format!("{}/{}", base, rel)).ok())
+        .map(|flag| flag.trim() != "0")
+}
+
+#[cfg(not(target_os = "linux"))]
+fn device_rotational_flag(_device: u64) -> Option<bool> {
+    None
+}
+
+/// Sort key for reading a file in disk order: (0, physical offset of its
+/// first extent) via FIEMAP on Linux, else (1, inode number), else (2, 0).
+/// Archive members go by their archive file.
+fn disk_order_key(file: &FileInfo) -> (u8, u64) {
+    let path = file.archive_member.as_ref().map_or(file.path.as_path(), |m| m.archive.as_path());
+    #[cfg(target_os = "linux")]
+    if let Some(physical) = first_physical_extent(path) {
+        return (0, physical);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if let Ok(metadata) = std::fs::metadata(path) {
+            return (1, metadata.ino());
+        }
+    }
+    (2, 0)
+}
+
+/// Physical byte offset of the first extent of `path` (FS_IOC_FIEMAP), or
+/// None if the filesystem does not map extents or the file has none.
+#[cfg(target_os = "linux")]
+fn first_physical_extent(path: &Path) -> Option<u64> {
+    use std::os::unix::io::AsRawFd;
+
+    // Layouts from linux/fiemap.h, asking for a single extent
+    #[repr(C)]
+    #[derive(Default)]
+    #[allow(dead_code)]
+    struct FiemapExtent {
+        fe_logical: u64,
+        fe_physical: u64,
+        fe_length: u64,
+        fe_reserved64: [u64; 2],
+        fe_flags: u32,
+        fe_reserved: [u32; 3],
+    }
+    #[repr(C)]
+    #[derive(Default)]
+    #[allow(dead_code)]
+    struct Fiemap {
+        fm_start: u64,
+        fm_length: u64,
+        fm_flags: u32,
+        fm_mapped_extents: u32,
+        fm_extent_count: u32,
+        fm_reserved: u32,
+        fm_extents: [FiemapExtent; 1],
+    }
+    const FS_IOC_FIEMAP: u32 = 0xC020_660B; // _IOWR('f', 11, struct fiemap)
+
+    let file = File::open(path).ok()?;
+    let mut map = Fiemap { fm_length: u64::MAX, fm_extent_count: 1, ..Default::default() };
+    // SAFETY: `map` is a valid fiemap header with room for the one extent
+    // requested in fm_extent_count, and outlives the call.
+    let rc = unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_FIEMAP as _, &mut map as *mut Fiemap) };
+    (rc == 0 && map.fm_mapped_extents > 0).then_some(map.fm_extents[0].fe_physical)
+}
+
+/// One device's pending jobs for run_per_device, with their job indices and
+/// the second device they read from, if any.
+type JobQueue<J> = Mutex<std::vec::IntoIter<(usize, Option<u64>, J)>>;
+
+/// Reader slots per device for run_per_device: a job takes a slot on every
+/// device it reads from before it starts.
+struct DeviceSlots {
+    free: Mutex<HashMap<u64, usize>>,
+    released: std::sync::Condvar,
+}
+
+impl DeviceSlots {
+    /// Take a slot on each device, lowest device first. Every job does it
+    /// in that order, so two jobs can never wait on each other.
+    fn acquire(&self, devices: &[u64]) {
+        let mut free = self.free.lock().unwrap();
+        for device in devices {
+            while free[device] == 0 {
+                free = self.released.wait(free).unwrap();
+            }
+            *free.get_mut(device).unwrap() -= 1;
+        }
+    }
+
+    fn release(&self, devices: &[u64]) {
+        let mut free = self.free.lock().unwrap();
+        for device in devices {
+            *free.get_mut(device).unwrap() += 1;
+        }
+        self.released.notify_all();
+    }
+}
+
+/// Run `work` over `jobs`, each tagged with the device (st_dev) it reads
+/// from and optionally a second one (a pair compared across two disks).
+/// Every device gets its own `per_device(device)` worker threads and reader
+/// slots, so a slow disk never has more readers than that and never holds
+/// up a fast one. A two-device job runs on the lower device's queue and also
+/// holds a slot on the other device while it reads.
+/// Results come back in job order; the first error is returned.
+fn run_per_device<J, R, F>(
+    jobs: Vec<(u64, Option<u64>, J)>,
+    per_device: impl Fn(u64) -> usize,
+    debug: bool,
+    work: F,
+) -> io::Result<Vec<R>>
+where
+    J: Send,
+    R: Send,
+    F: Fn(J) -> io::Result<R> + Sync,
+{
+    let job_count = jobs.len();
+    let mut queues: HashMap<u64, Vec<(usize, Option<u64>, J)>> = HashMap::new();
+    let mut devices = HashSet::new();
+    for (index, (device, other, job)) in jobs.into_iter().enumerate() {
+        let (first, second) = match other {
+            Some(other) if other != device => (device.min(other), Some(device.max(other))),
+            _ => (device, None),
+        };
+        devices.insert(first);
+        devices.extend(second);
+        queues.entry(first).or_default().push((index, second, job));
+    }
+    if debug {
+        for (device, queue) in &queues {
+            let shared = queue.iter().filter(|(_, second, _)| second.is_some()).count();
+            println!(
+                "Device {:#x}: {} job(s) ({} shared with another device) on up to {} thread(s)",
+                device, queue.len(), shared, per_device(*device)
+            );
+        }
+    }
+
+    let slots = DeviceSlots {
+        free: Mutex::new(devices.into_iter().map(|d| (d, per_device(d))).collect()),
+        released: std::sync::Condvar::new(),
+    };
+    let results: Mutex<Vec<Option<R>>> = Mutex::new((0..job_count).map(|_| None).collect());
+    let queues: Vec<(u64, usize, JobQueue<J>)> = queues
+        .into_iter()
+        .map(|(device, queue)| (device, queue.len().min(per_device(device)), Mutex::new(queue.into_iter())))
+        .collect();
+    let failed = AtomicBool::new(false);
+
+    thread::scope(|scope| -> io::Result<()> {
+        let mut handles = Vec::new();
+        for (device, workers, queue) in &queues {
+            for _ in 0..*workers {
+                handles.push(scope.spawn(|| -> io::Result<()> {
+                    while !failed.load(Ordering::Relaxed) {
+                        let Some((index, second, job)) = queue.lock().unwrap().next() else { break };
+                        let held: Vec<u64> = std::iter::once(*device).chain(second).collect();
+                        slots.acquire(&held);
+                        let outcome = work(job);
+                        slots.release(&held);
+                        match outcome {
+                            Ok(result) => results.lock().unwrap()[index] = Some(result),
+                            Err(e) => {
+                                failed.store(true, Ordering::Relaxed);
+                                return Err(e);
+                            }
+                        }
+                    }
+                    Ok(())
+                }));
+            }
+        }
+        for handle in handles {
+            handle.join().unwrap()?;
+        }
+        Ok(())
+    })?;
+
+    Ok(results.into_inner().unwrap().into_iter().map(|r| r.expect("every job ran")).collect())
+}
+
 fn has_files_from_both_folders(files: &[FileInfo]) -> bool {
     let mut found_folder0 = false;
     let mut found_folder1 = false;
@@ -837,6 +1983,507 @@ fn group_by_name(files: &[FileInfo]) -> Vec<Vec<FileInfo>> {
     name_groups.into_values().collect()
 }
 
+/// Give every scanned file a content id: all members of the same exact group
+/// share one, every other file gets a fresh one. Files that never met a
+/// same-content file on the other side are thereby treated as unique.
+/// Quick-check groups only count with --trust-quick (`trust_quick`);
+/// otherwise they are confirmed by confirm_quick_matches beforehand.
+fn assign_content_ids<'a>(
+    scan_index: &'a HashMap<PathBuf, FileInfo>,
+    duplicates: &[DuplicateGroup],
+    trust_quick: bool,
+) -> HashMap<&'a Path, u64> {
+    let mut content_ids: HashMap<&Path, u64> = HashMap::new();
+    let mut next_id = 0u64;
+    let counts = |kind: &MatchKind| *kind == MatchKind::Exact || (trust_quick && *kind == MatchKind::QuickMatch);
+    for group in duplicates.iter().filter(|g| counts(&g.match_kind)) {
+        for path in group.files_by_folder.iter().flatten() {
+            if let Some((key, _)) = scan_index.get_key_value(path) {
+                content_ids.insert(key.as_path(), next_id);
+            }
+        }
+        next_id += 1;
+    }
+    let mut rest: Vec<&PathBuf> = scan_index.keys().filter(|p| !content_ids.contains_key(p.as_path())).collect();
+    rest.sort();
+    for path in rest {
+        content_ids.insert(path.as_path(), next_id);
+        next_id += 1;
+    }
+    content_ids
+}
+
+/// --dirs: find duplicate directories from the per-file groups.
+/// Every scanned file gets a content id (see assign_content_ids); each
+/// directory then gets a Merkle-style
+/// digest over its file names/content ids and its subdirectories' digests.
+/// Directories with equal digests are identical subtrees. Directories whose
+/// every (relative path, content) entry also appears in a directory on the
+/// other side are reported as subsets. Only the topmost pair is reported;
+/// the scan roots themselves are never compared.
+fn find_duplicate_dirs(
+    scan_index: &HashMap<PathBuf, FileInfo>,
+    duplicates: &[DuplicateGroup],
+    folder1: &str,
+    folder2: &str,
+    options: &CompareOptions,
+) -> Vec<DirDuplicateGroup> {
+    println!("Comparing directories...");
+
+    let content_ids = assign_content_ids(scan_index, duplicates, options.trust_quick);
+
+    // Build the directory tree from the file paths.
+    let roots = [Path::new(folder1), Path::new(folder2)];
+    let mut nodes: HashMap<PathBuf, DirNode> = HashMap::new();
+    let mut all_files: Vec<&FileInfo> = scan_index.values().collect();
+    all_files.sort_by(|a, b| a.path.cmp(&b.path));
+    for file in all_files {
+        let root = roots[file.folder_index];
+        let id = content_ids[file.path.as_path()];
+        let parent = match file.path.parent() {
+            Some(p) if p != root => p,
+            _ => continue, // files directly in the root don't belong to any subdirectory
+        };
+        let name = file.path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let node = nodes.entry(parent.to_path_buf()).or_default();
+        node.folder_index = file.folder_index;
+        node.files.push((name, id, file.size));
+
+        // Register the chain of ancestors up to (excluding) the root
+        let mut child = parent;
+        while let Some(up) = child.parent() {
+            if up == root || !up.starts_with(root) {
+                break;
+            }
+            let up_node = nodes.entry(up.to_path_buf()).or_default();
+            up_node.folder_index = file.folder_index;
+            if up_node.subdirs.iter().any(|d| d == child) {
+                break; // rest of the chain is already registered
+            }
+            up_node.subdirs.push(child.to_path_buf());
+            child = up;
+        }
+    }
+
+    // Digests and recursive (relative path, content id) sets, bottom-up.
+    let mut dir_paths: Vec<PathBuf> = nodes.keys().cloned().collect();
+    dir_paths.sort_by_key(|p| std::cmp::Reverse(p.components().count()));
+    let mut digests: HashMap<PathBuf, String> = HashMap::new();
+    let mut contents: HashMap<PathBuf, HashSet<(PathBuf, u64)>> = HashMap::new();
+    let mut sizes: HashMap<PathBuf, u64> = HashMap::new();
+    for dir in &dir_paths {
+        let node = &nodes[dir];
+        let mut entries: Vec<String> = Vec::new();
+        let mut content: HashSet<(PathBuf, u64)> = HashSet::new();
+        let mut size = 0u64;
+        for (name, id, file_size) in &node.files {
+            entries.push(format!("f\0{}\0{}", name, id));
+            content.insert((PathBuf::from(name), *id));
+            size += file_size;
+        }
+        for sub in &node.subdirs {
+            let name = sub.file_name().unwrap_or_default().to_string_lossy().to_string();
+            entries.push(format!("d\0{}\0{}", name, digests[sub]));
+            for (rel, id) in &contents[sub] {
+                content.insert((Path::new(&name).join(rel), *id));
+            }
+            size += sizes[sub];
+        }
+        entries.sort();
+        let mut hasher = Context::new();
+        for entry in &entries {
+            hasher.consume(entry.as_bytes());
+            hasher.consume(b"\n");
+        }
+        digests.insert(dir.clone(), format!("{:x}", hasher.compute()));
+        contents.insert(dir.clone(), content);
+        sizes.insert(dir.clone(), size);
+    }
+
+    // Identical subtrees: same digest.
+    let mut by_digest: HashMap<&str, Vec<&PathBuf>> = HashMap::new();
+    for dir in &dir_paths {
+        by_digest.entry(digests[dir].as_str()).or_default().push(dir);
+    }
+    let mut identical_dirs: HashSet<&Path> = HashSet::new();
+    let mut identical_sets: Vec<Vec<&PathBuf>> = Vec::new();
+    for dirs in by_digest.into_values() {
+        let spans_both = dirs.iter().any(|d| nodes[*d].folder_index == 0)
+            && dirs.iter().any(|d| nodes[*d].folder_index == 1);
+        if dirs.len() > 1 && (spans_both || options.intra_folder) {
+            identical_dirs.extend(dirs.iter().map(|d| d.as_path()));
+            identical_sets.push(dirs);
+        }
+    }
+
+    let mut results = Vec::new();
+    for dirs in identical_sets {
+        // Implied by an identical parent pair: don't report it again.
+        if dirs.iter().all(|d| d.parent().is_some_and(|p| identical_dirs.contains(p))) {
+            continue;
+        }
+        let mut dirs_by_folder = vec![Vec::new(), Vec::new()];
+        for dir in &dirs {
+            dirs_by_folder[nodes[*dir].folder_index].push((*dir).clone());
+        }
+        for side in dirs_by_folder.iter_mut() {
+            side.sort();
+        }
+        results.push(DirDuplicateGroup {
+            dirs_by_folder,
+            file_count: contents[dirs[0]].len(),
+            size: sizes[dirs[0]],
+            kind: DirMatchKind::Identical,
+        });
+    }
+
+    // Subsets: only between folders. Index each content id to the directories
+    // of each side that contain it somewhere in their subtree.
+    let mut containing: [HashMap<u64, Vec<&PathBuf>>; 2] = [HashMap::new(), HashMap::new()];
+    for dir in &dir_paths {
+        let side = nodes[dir].folder_index;
+        for (_, id) in &contents[dir] {
+            let list = containing[side].entry(*id).or_default();
+            if list.last() != Some(&dir) {
+                list.push(dir);
+            }
+        }
+    }
+
+    // Top-down, so a reported parent pair can suppress its children.
+    let mut subset_pairs: HashSet<(&Path, &Path)> = HashSet::new();
+    let mut top_down: Vec<&PathBuf> = dir_paths.iter().collect();
+    top_down.sort_by_key(|p| p.components().count());
+    for small in top_down {
+        let side = nodes[small].folder_index;
+        let other = 1 - side;
+        let small_content = &contents[small];
+        let probe = match small_content.iter().next() {
+            Some((_, id)) => *id,
+            None => continue,
+        };
+        let candidates = match containing[other].get(&probe) {
+            Some(c) => c,
+            None => continue,
+        };
+        for big in candidates {
+            let big_content = &contents[*big];
+            if big_content.len() <= small_content.len() || !small_content.is_subset(big_content) {
+                continue;
+            }
+            // Implied by the parent pair (same child name under a reported pair)
+            let implied = match (small.parent(), big.parent()) {
+                (Some(sp), Some(bp)) => {
+                    small.file_name() == big.file_name() && subset_pairs.contains(&(sp, bp))
+                }
+                _ => false,
+            };
+            subset_pairs.insert((small.as_path(), big.as_path()));
+            if implied {
+                continue;
+            }
+            let extra_files = big_content.len() - small_content.len();
+            let mut dirs_by_folder = vec![Vec::new(), Vec::new()];
+            dirs_by_folder[side].push(small.clone());
+            dirs_by_folder[other].push((*big).clone());
+            results.push(DirDuplicateGroup {
+                dirs_by_folder,
+                file_count: small_content.len(),
+                size: sizes[small],
+                kind: if side == 0 {
+                    DirMatchKind::Folder1Subset { extra_files }
+                } else {
+                    DirMatchKind::Folder2Subset { extra_files }
+                },
+            });
+        }
+    }
+
+    // Outermost groups first: a directory inside one that is already reported
+    // (e.g. an identical pair within a subset pair) is covered by that entry,
+    // and would only be refused once its parent has been moved.
+    results.sort_by_key(|g| g.dirs_by_folder.iter().flatten().map(|d| d.components().count()).min());
+    let mut reported: HashSet<PathBuf> = HashSet::new();
+    let mut results: Vec<DirDuplicateGroup> = results
+        .into_iter()
+        .filter_map(|mut group| {
+            for side in group.dirs_by_folder.iter_mut() {
+                side.retain(|d| !d.ancestors().skip(1).any(|a| reported.contains(a)));
+            }
+            let keep = match group.kind {
+                DirMatchKind::Identical => {
+                    let spans_both = group.dirs_by_folder.iter().all(|side| !side.is_empty());
+                    group.dirs_by_folder.iter().flatten().count() > 1 && (spans_both || options.intra_folder)
+                }
+                _ => group.dirs_by_folder.iter().all(|side| !side.is_empty()),
+            };
+            if !keep {
+                return None;
+            }
+            reported.extend(group.dirs_by_folder.iter().flatten().cloned());
+            Some(group)
+        })
+        .collect();
+
+    results.sort_by(|a, b| {
+        let path_a = a.dirs_by_folder.iter().flatten().next();
+        let path_b = b.dirs_by_folder.iter().flatten().next();
+        path_a.cmp(&path_b)
+    });
+
+    println!("Found {} duplicate directory group(s)", results.len());
+    results
+}
+
+/// --diff: classify every file by relative path and content id.
+/// A relative path present on both sides is either identical (not listed)
+/// or `Changed`. Otherwise, content that exists on the other side is
+/// `Moved` (one entry per content, listing all non-identical paths on
+/// each side); anything else is only in its own folder.
+fn find_folder_diff(
+    scan_index: &HashMap<PathBuf, FileInfo>,
+    duplicates: &[DuplicateGroup],
+    folder1: &str,
+    folder2: &str,
+    trust_quick: bool,
+) -> Vec<DiffEntry> {
+    let content_ids = assign_content_ids(scan_index, duplicates, trust_quick);
+    let roots = [Path::new(folder1), Path::new(folder2)];
+
+    // relative path -> file, per side; content id -> files, per side
+    let mut by_relative: [HashMap<&Path, &FileInfo>; 2] = [HashMap::new(), HashMap::new()];
+    let mut by_content: [HashMap<u64, Vec<&FileInfo>>; 2] = [HashMap::new(), HashMap::new()];
+    for file in scan_index.values() {
+        let side = file.folder_index;
+        let relative = file.path.strip_prefix(roots[side]).unwrap_or(&file.path);
+        by_relative[side].insert(relative, file);
+        by_content[side].entry(content_ids[file.path.as_path()]).or_default().push(file);
+    }
+
+    let mut entries = Vec::new();
+    let mut moved: HashMap<u64, DiffEntry> = HashMap::new();
+
+    for side in 0..2 {
+        let other = 1 - side;
+        for (relative, file) in &by_relative[side] {
+            let id = content_ids[file.path.as_path()];
+            if let Some(counterpart) = by_relative[other].get(relative) {
+                // Same relative path on both sides: report once, from folder1
+                if side == 0 && content_ids[counterpart.path.as_path()] != id {
+                    entries.push(DiffEntry {
+                        status: DiffStatus::Changed,
+                        folder1_paths: vec![file.path.clone()],
+                        folder2_paths: vec![counterpart.path.clone()],
+                        size: file.size,
+                    });
+                }
+                if content_ids[counterpart.path.as_path()] == id {
+                    continue;
+                }
+            }
+
+            if by_content[other].contains_key(&id) {
+                let entry = moved.entry(id).or_insert_with(|| DiffEntry {
+                    status: DiffStatus::Moved,
+                    folder1_paths: Vec::new(),
+                    folder2_paths: Vec::new(),
+                    size: file.size,
+                });
+                if side == 0 {
+                    entry.folder1_paths.push(file.path.clone());
+                } else {
+                    entry.folder2_paths.push(file.path.clone());
+                }
+            } else if by_relative[other].contains_key(relative) {
+                // Already reported as Changed
+                continue;
+            } else {
+                let (status, folder1_paths, folder2_paths) = if side == 0 {
+                    (DiffStatus::OnlyInFolder1, vec![file.path.clone()], Vec::new())
+                } else {
+                    (DiffStatus::OnlyInFolder2, Vec::new(), vec![file.path.clone()])
+                };
+                entries.push(DiffEntry { status, folder1_paths, folder2_paths, size: file.size });
+            }
+        }
+    }
+
+    // A moved entry may only have non-identical paths on one side (e.g. an
+    // extra copy in folder2 of a file that is also unchanged in place):
+    // fill the empty side with every file of that content.
+    for (id, mut entry) in moved {
+        if entry.folder1_paths.is_empty() {
+            entry.folder1_paths = by_content[0][&id].iter().map(|f| f.path.clone()).collect();
+        }
+        if entry.folder2_paths.is_empty() {
+            entry.folder2_paths = by_content[1][&id].iter().map(|f| f.path.clone()).collect();
+        }
+        entry.folder1_paths.sort();
+        entry.folder2_paths.sort();
+        entries.push(entry);
+    }
+
+    entries.sort_by(|a, b| {
+        let path_a = a.folder1_paths.iter().chain(a.folder2_paths.iter()).next();
+        let path_b = b.folder1_paths.iter().chain(b.folder2_paths.iter()).next();
+        a.status.cmp(&b.status).then(path_a.cmp(&path_b))
+    });
+    entries
+}
+
+/// --renames: pair up files with identical content but different relative
+/// paths. Paths that are identical on both sides are left out; the rest of
+/// each content's folder1 and folder2 paths are paired greedily, preferring
+/// a partner with the same file name, then one in the same directory.
+/// Extra copies that find no partner are not reported.
+fn find_renames(
+    scan_index: &HashMap<PathBuf, FileInfo>,
+    duplicates: &[DuplicateGroup],
+    folder1: &str,
+    folder2: &str,
+    trust_quick: bool,
+) -> Vec<RenamePair> {
+    let content_ids = assign_content_ids(scan_index, duplicates, trust_quick);
+    let roots = [Path::new(folder1), Path::new(folder2)];
+    let relative = |f: &FileInfo| f.path.strip_prefix(roots[f.folder_index]).unwrap_or(&f.path).to_path_buf();
+
+    let mut by_content: HashMap<u64, [Vec<(PathBuf, &FileInfo)>; 2]> = HashMap::new();
+    for file in scan_index.values() {
+        by_content
+            .entry(content_ids[file.path.as_path()])
+            .or_default()[file.folder_index]
+            .push((relative(file), file));
+    }
+
+    let mut pairs = Vec::new();
+    for [mut side1, mut side2] in by_content.into_values() {
+        if side1.is_empty() || side2.is_empty() {
+            continue;
+        }
+        // Unchanged in place: not a rename
+        let rel2: HashSet<PathBuf> = side2.iter().map(|(r, _)| r.clone()).collect();
+        let rel1: HashSet<PathBuf> = side1.iter().map(|(r, _)| r.clone()).collect();
+        side1.retain(|(r, _)| !rel2.contains(r));
+        side2.retain(|(r, _)| !rel1.contains(r));
+        side1.sort_by(|a, b| a.0.cmp(&b.0));
+        side2.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for (rel_a, file_a) in side1 {
+            let best = side2
+                .iter()
+                .enumerate()
+                .max_by_key(|(i, (rel_b, _))| {
+                    (rel_a.file_name() == rel_b.file_name(), rel_a.parent() == rel_b.parent(), std::cmp::Reverse(*i))
+                })
+                .map(|(i, _)| i);
+            let (rel_b, file_b) = match best {
+                Some(i) => side2.remove(i),
+                None => break,
+            };
+            let kind = match (rel_a.parent() == rel_b.parent(), rel_a.file_name() == rel_b.file_name()) {
+                (true, _) => RenameKind::Renamed,
+                (false, true) => RenameKind::Moved,
+                (false, false) => RenameKind::MovedAndRenamed,
+            };
+            pairs.push(RenamePair {
+                kind,
+                folder1_path: file_a.path.clone(),
+                folder2_path: file_b.path.clone(),
+                size: file_a.size,
+            });
+        }
+    }
+
+    pairs.sort_by(|a, b| a.kind.cmp(&b.kind).then(a.folder1_path.cmp(&b.folder1_path)));
+    pairs
+}
+
+/// The moves a rename script makes, in order: each folder2 file of a pair
+/// goes to the relative path its counterpart has in folder1. A file whose
+/// path is itself the target of another move (a swap a↔b or a chain a→b,
+/// b→c) is first moved aside to a temporary name, so no move finds its
+/// target still taken by a file that is about to leave. Returns the moves
+/// and the pairs that needed a temporary name.
+fn plan_rename_moves<'a>(pairs: &'a [RenamePair], folder1: &str, folder2: &str) -> (Vec<(PathBuf, PathBuf)>, Vec<&'a RenamePair>) {
+    let targets: Vec<PathBuf> = pairs
+        .iter()
+        .map(|pair| Path::new(folder2).join(pair.folder1_path.strip_prefix(folder1).unwrap_or(&pair.folder1_path)))
+        .collect();
+    let taken: HashSet<&Path> = targets.iter().map(|t| t.as_path()).collect();
+
+    let mut moves = Vec::new();
+    let mut rerouted = Vec::new();
+    let mut sources = Vec::new();
+    for (index, pair) in pairs.iter().enumerate() {
+        if taken.contains(pair.folder2_path.as_path()) {
+            let name = pair.folder2_path.file_name().unwrap_or_default().to_string_lossy();
+            let temporary = pair.folder2_path.with_file_name(format!("{}.duptool-rename-{}", name, index));
+            moves.push((pair.folder2_path.clone(), temporary.clone()));
+            rerouted.push(pair);
+            sources.push(temporary);
+        } else {
+            sources.push(pair.folder2_path.clone());
+        }
+    }
+    moves.extend(sources.into_iter().zip(targets));
+    (moves, rerouted)
+}
+
+/// Write a script that renames each folder2 file of a rename pair to the
+/// relative path its counterpart has in folder1 (see plan_rename_moves).
+/// Uses non-overwriting moves, so a target that is already taken by a file
+/// outside the script is left alone.
+fn write_rename_script(pairs: &[RenamePair], folder1: &str, folder2: &str, script: &Path) -> io::Result<()> {
+    use std::io::Write;
+
+    let windows = script
+        .extension()
+        .map(|e| e.eq_ignore_ascii_case("bat") || e.eq_ignore_ascii_case("cmd"))
+        .unwrap_or(false);
+    let mut out = io::BufWriter::new(File::create(script)?);
+
+    // cmd.exe: a literal percent sign is written as %%
+    let bat_escape = |text: &str| text.replace('%', "%%");
+    let bat_quote = |p: &Path| format!("\"{}\"", bat_escape(&p.to_string_lossy()));
+
+    if windows {
+        writeln!(out, "@echo off")?;
+        // The script is UTF-8: switch the console code page so non-ASCII paths survive
+        writeln!(out, "chcp 65001 >nul")?;
+        writeln!(out, "rem Generated by duptool {}: rename {} to match the layout of {}",
+            env!("CARGO_PKG_VERSION"), bat_escape(folder2), bat_escape(folder1))?;
+    } else {
+        writeln!(out, "#!/bin/sh")?;
+        writeln!(out, "# Generated by duptool {}: rename {} to match the layout of {}",
+            env!("CARGO_PKG_VERSION"), folder2, folder1)?;
+        writeln!(out, "# 'mv -n' never overwrites: targets that already exist are skipped.")?;
+    }
+
+    let (moves, rerouted) = plan_rename_moves(pairs, folder1, folder2);
+    if !rerouted.is_empty() {
+        println!("{} rename(s) form swaps or chains and go through a temporary name:", rerouted.len());
+        for pair in &rerouted {
+            println!("  {} -> {}", pair.folder2_path.display(), pair.folder1_path.display());
+        }
+    }
+
+    // POSIX shell single-quoting: close, escaped quote, reopen
+    let sh_quote = |p: &Path| format!("'{}'", p.to_string_lossy().replace('\'', "'\\''"));
+    for (source, target) in &moves {
+        let parent = target.parent().unwrap_or(Path::new(folder2));
+        if windows {
+            writeln!(out, "if not exist {} mkdir {}", bat_quote(parent), bat_quote(parent))?;
+            writeln!(out, "if not exist {} move {} {}",
+                bat_quote(target), bat_quote(source), bat_quote(target))?;
+        } else {
+            writeln!(out, "mkdir -p {} && mv -n {} {}",
+                sh_quote(parent), sh_quote(source), sh_quote(target))?;
+        }
+    }
+
+    out.flush()
+}
+
 #[allow(dead_code)]
 fn sync_content_compare(
     groups: Vec<Vec<FileInfo>>,
@@ -862,7 +2509,7 @@ fn sync_content_compare(
         let mut content_groups: HashMap<String, Vec<FileInfo>> = HashMap::new();
 
         for file in group {
-            match calculate_file_hash(file, options.quick_content_check)? {
+            match calculate_file_hash(file, options.quick_content_check.then_some(&options.quick_sampling))? {
                 Some(hash) => {
                     content_groups.entry(hash).or_default().push(file.clone());
                 },
@@ -914,7 +2561,17 @@ fn sync_content_compare_with_file_progress(
     options: &CompareOptions,
     progress_bar: ProgressBar,
     file_progress: ProgressBar,
+    pair_stats: Arc<Mutex<PairCompareStats>>,
 ) -> io::Result<Vec<DuplicateGroup>> {
+    // --hdd: read each group's files, and the groups, in physical disk order
+    let mut groups = groups;
+    if options.hdd_optimized {
+        for group in &mut groups {
+            sort_by_disk_order(group, |f| f, false);
+        }
+        sort_by_disk_order(&mut groups, |g| &g[0], options.debug);
+    }
+
     let mut duplicates = Vec::new();
     let mut total_size_processed: u64 = 0;
     let mut last_eta_update = Instant::now();
@@ -933,29 +2590,38 @@ fn sync_content_compare_with_file_progress(
         let group_size: u64 = group.iter().map(|file| file.size).sum();
         let mut content_groups: HashMap<String, Vec<FileInfo>> = HashMap::new();
 
-        for file in group {
-            match calculate_file_hash(file, options.quick_content_check)? {
-                Some(hash) => {
-                    content_groups.entry(hash).or_default().push(file.clone());
-                },
-                None => continue,
-            }
-            // Update processed size and ETA more frequently (per file)
-            total_size_processed += file.size;
-            file_progress.inc(1);
-            if last_eta_update.elapsed() >= eta_update_interval {
-                let elapsed = start_time.elapsed().as_secs_f64();
-                let avg_speed = if elapsed > 0.0 { total_size_processed as f64 / elapsed } else { 0.0 };
-                let remaining_size = total_size.saturating_sub(total_size_processed);
-                let eta = if avg_speed > 0.0 { remaining_size as f64 / avg_speed } else { 0.0 };
-                progress_bar.set_message(format!(
-                    "ETA: {:.1}s (processed: {:.2} GB, remaining: {:.2} GB, speed: {:.2} MB/s)",
-                    eta,
-                    total_size_processed as f64 / (1024.0 * 1024.0 * 1024.0),
-                    remaining_size as f64 / (1024.0 * 1024.0 * 1024.0),
-                    avg_speed / (1024.0 * 1024.0)
-                ));
-                last_eta_update = Instant::now();
+        // Two files: compare them directly, stopping at the first difference
+        if let Some(equal) = compare_pair_directly(group, &pair_stats)? {
+            if equal {
+                content_groups.insert(String::new(), group.clone());
+            }
+            total_size_processed += group_size;
+            file_progress.inc(2);
+        } else {
+            for file in group {
+                match calculate_file_hash(file, options.quick_content_check.then_some(&options.quick_sampling))? {
+                    Some(hash) => {
+                        content_groups.entry(hash).or_default().push(file.clone());
+                    },
+                    None => continue,
+                }
+                // Update processed size and ETA more frequently (per file)
+                total_size_processed += file.size;
+                file_progress.inc(1);
+                if last_eta_update.elapsed() >= eta_update_interval {
+                    let elapsed = start_time.elapsed().as_secs_f64();
+                    let avg_speed = if elapsed > 0.0 { total_size_processed as f64 / elapsed } else { 0.0 };
+                    let remaining_size = total_size.saturating_sub(total_size_processed);
+                    let eta = if avg_speed > 0.0 { remaining_size as f64 / avg_speed } else { 0.0 };
+                    progress_bar.set_message(format!(
+                        "ETA: {:.1}s (processed: {:.2} GB, remaining: {:.2} GB, speed: {:.2} MB/s)",
+                        eta,
+                        total_size_processed as f64 / (1024.0 * 1024.0 * 1024.0),
+                        remaining_size as f64 / (1024.0 * 1024.0 * 1024.0),
+                        avg_speed / (1024.0 * 1024.0)
+                    ));
+                    last_eta_update = Instant::now();
+                }
             }
         }
 
@@ -982,137 +2648,122 @@ fn sync_content_compare_with_file_progress(
     Ok(duplicates)
 }
 
-// New: async_content_compare_with_file_progress
+/// A unit of work for the async full-hash stage.
+enum ContentJob {
+    Hash(FileInfo),
+    /// Two files compared directly, holding a reader slot on both devices.
+    Pair(Vec<FileInfo>),
+}
+
+/// Full-hash stage for -A/-E. Work is scheduled per device (run_per_device):
+/// every file is hashed by the workers of its own device, and two-file groups
+/// are compared directly instead, on one device or across two.
 fn async_content_compare_with_file_progress(
     groups: Vec<Vec<FileInfo>>, 
     options: CompareOptions, 
     progress_bar: ProgressBar,
     file_progress: ProgressBar,
+    pair_stats: Arc<Mutex<PairCompareStats>>,
 ) -> io::Result<Vec<DuplicateGroup>> {
-    let duplicates = Arc::new(Mutex::new(Vec::new()));
-    let progress = Arc::new(progress_bar);
-    let file_progress = Arc::new(file_progress);
-
     let total_size: u64 = groups.iter().flat_map(|g| g.iter()).map(|f| f.size).sum();
-    let processed_size = Arc::new(Mutex::new(0u64));
+    let processed_size = Mutex::new(0u64);
     let start_time = Instant::now();
     let eta_update_interval = std::time::Duration::from_millis(500);
-    let last_eta_update = Arc::new(Mutex::new(Instant::now()));
-
-    let num_threads = num_cpus::get();
-    let chunks = split_into_chunks(groups, num_threads, options.hdd_optimized);
-
-    let mut handles = Vec::new();
-
-    for chunk in chunks {
-        let duplicates = Arc::clone(&duplicates);
-        let progress = Arc::clone(&progress);
-        let file_progress = Arc::clone(&file_progress);
-        let options = options.clone();
-        let processed_size = Arc::clone(&processed_size);
-        let last_eta_update = Arc::clone(&last_eta_update);
-
-        let handle = thread::spawn(move || -> io::Result<()> {
-            // Local counters to reduce lock contention
-            let mut local_processed_size = 0u64;
-            let mut files_processed = 0u64;
-            
-            for group in chunk {
-                progress.inc(1);
-
-                if group.len() <= 1 || !has_files_from_both_folders(&group) {
-                    continue;
-                }
-
-                let mut content_groups: HashMap<String, Vec<FileInfo>> = HashMap::new();
-                let file_size = group[0].size;
-
-                for file in &group {
-                    match calculate_file_hash(file, options.quick_content_check)? {
-                        Some(hash) => {
-                            content_groups.entry(hash).or_default().push(file.clone());
-                        },
-                        None => continue,
-                    }
-                    
-                    // Update local counters (no locks!)
-                    local_processed_size += file.size;
-                    files_processed += 1;
-                    file_progress.inc(1);
-                    
-                    // Only update shared state occasionally to reduce lock contention
-                    if files_processed % 10 == 0 {  // Update every 10 files instead of every file
-                        let mut sz = processed_size.lock().unwrap();
-                        *sz += local_processed_size;
-                        local_processed_size = 0;  // Reset local counter
-                        
-                        let mut last = last_eta_update.lock().unwrap();
-                        if last.elapsed() >= eta_update_interval {
-                            let elapsed = start_time.elapsed().as_secs_f64();
-                            let avg_speed = if elapsed > 0.0 { *sz as f64 / elapsed } else { 0.0 };
-                            let remaining_size = total_size.saturating_sub(*sz);
-                            let eta = if avg_speed > 0.0 { remaining_size as f64 / avg_speed } else { 0.0 };
-                            progress.set_message(format!(
-                                "ETA: {:.1}s (processed: {:.2} GB, remaining: {:.2} GB, speed: {:.2} MB/s)",
-                                eta,
-                                *sz as f64 / (1024.0 * 1024.0 * 1024.0),
-                                remaining_size as f64 / (1024.0 * 1024.0 * 1024.0),
-                                avg_speed / (1024.0 * 1024.0)
-                            ));
-                            *last = Instant::now();
-                        }
-                    }
-                }
-
-                let mut local_duplicates = Vec::new();
-
-                for (_, content_group) in content_groups {
-                    if content_group.len() > 1 && has_files_from_both_folders(&content_group) {
-                        let mut files_by_folder = vec![Vec::new(), Vec::new()];
-
-                        for file in content_group {
-                            files_by_folder[file.folder_index].push(file.path);
-                        }
+    let last_eta_update = Mutex::new(Instant::now());
+
+    // Jobs left per group, so the group bar moves when a group is done
+    let mut jobs = Vec::new();
+    let mut jobs_left = Vec::new();
+    for (group_index, group) in groups.iter().enumerate() {
+        if group.len() <= 1 || !has_files_from_both_folders(group) {
+            progress_bar.inc(1);
+            jobs_left.push(AtomicUsize::new(0));
+            continue;
+        }
+        let pair = group.len() == 2 && group.iter().all(|f| f.archive_member.is_none());
+        if pair {
+            let job = ContentJob::Pair(group.clone());
+            jobs.push((group[0].device, Some(group[1].device), (group_index, job)));
+            jobs_left.push(AtomicUsize::new(1));
+        } else {
+            jobs.extend(group.iter().map(|f| (f.device, None, (group_index, ContentJob::Hash(f.clone())))));
+            jobs_left.push(AtomicUsize::new(group.len()));
+        }
+    }
 
-                        local_duplicates.push(DuplicateGroup { 
-                            files_by_folder, 
-                            size: file_size,
-                            match_kind: MatchKind::Exact,
-                        });
-                    }
-                }
+    if options.hdd_optimized {
+        sort_by_disk_order(&mut jobs, |(_, _, (_, job))| match job {
+            ContentJob::Hash(file) => file,
+            ContentJob::Pair(files) => &files[0],
+        }, options.debug);
+    }
 
-                if !local_duplicates.is_empty() {
-                    let mut all_duplicates = duplicates.lock().unwrap();
-                    all_duplicates.extend(local_duplicates);
-                }
+    let results = run_per_device(jobs, |device| readers_per_device(device, &options), options.debug, |(group_index, job)| {
+        let (bytes, result) = match job {
+            ContentJob::Hash(file) => {
+                let hash = calculate_file_hash(&file, options.quick_content_check.then_some(&options.quick_sampling))?;
+                file_progress.inc(1);
+                (file.size, (group_index, vec![file], hash))
             }
-            
-            // Flush any remaining local counter at the end
-            if local_processed_size > 0 {
-                let mut sz = processed_size.lock().unwrap();
-                *sz += local_processed_size;
+            ContentJob::Pair(files) => {
+                let equal = compare_pair_directly(&files, &pair_stats)?.unwrap_or(false);
+                file_progress.inc(2);
+                let bytes = files.iter().map(|f| f.size).sum();
+                (bytes, (group_index, files, equal.then(String::new)))
             }
+        };
+        if jobs_left[group_index].fetch_sub(1, Ordering::Relaxed) == 1 {
+            progress_bar.inc(1);
+        }
 
-            Ok(())
-        });
-
-        handles.push(handle);
+        let mut sz = processed_size.lock().unwrap();
+        *sz += bytes;
+        let mut last = last_eta_update.lock().unwrap();
+        if last.elapsed() >= eta_update_interval {
+            let elapsed = start_time.elapsed().as_secs_f64();
+            let avg_speed = if elapsed > 0.0 { *sz as f64 / elapsed } else { 0.0 };
+            let remaining_size = total_size.saturating_sub(*sz);
+            let eta = if avg_speed > 0.0 { remaining_size as f64 / avg_speed } else { 0.0 };
+            progress_bar.set_message(format!(
+                "ETA: {:.1}s (processed: {:.2} GB, remaining: {:.2} GB, speed: {:.2} MB/s)",
+                eta,
+                *sz as f64 / (1024.0 * 1024.0 * 1024.0),
+                remaining_size as f64 / (1024.0 * 1024.0 * 1024.0),
+                avg_speed / (1024.0 * 1024.0)
+            ));
+            *last = Instant::now();
+        }
+        Ok(result)
+    })?;
+
+    // Regroup by content: a matching pair shares the empty "hash"
+    let mut content_groups: Vec<HashMap<String, Vec<FileInfo>>> = vec![HashMap::new(); groups.len()];
+    for (group_index, files, hash) in results {
+        if let Some(hash) = hash {
+            content_groups[group_index].entry(hash).or_default().extend(files);
+        }
     }
 
-    for handle in handles {
-        handle.join().unwrap()?;
+    let mut duplicates = Vec::new();
+    for (group, content_groups) in groups.iter().zip(content_groups) {
+        for (_, content_group) in content_groups {
+            if content_group.len() > 1 && has_files_from_both_folders(&content_group) {
+                let mut files_by_folder = vec![Vec::new(), Vec::new()];
+                for file in content_group {
+                    files_by_folder[file.folder_index].push(file.path);
+                }
+                duplicates.push(DuplicateGroup {
+                    files_by_folder,
+                    size: group[0].size,
+                    match_kind: MatchKind::Exact,
+                });
+            }
+        }
     }
 
-    progress.finish();
+    progress_bar.finish();
     file_progress.finish();
-
-    let result = Arc::try_unwrap(duplicates)
-        .expect("Still have multiple owners of duplicates")
-        .into_inner()
-        .unwrap();
-
-    Ok(result)
+    Ok(duplicates)
 }
 
 // Helper: process files for one folder independently
@@ -1134,7 +2785,7 @@ fn process_files_independent(
         // Group by full hash
         let mut hash_map: HashMap<String, Vec<FileInfo>> = HashMap::new();
         for f in &group {
-            if let Some(h) = calculate_file_hash(f, false).ok().flatten() {
+            if let Some(h) = calculate_file_hash(f, None).ok().flatten() {
                 hash_map.entry(h).or_default().push(f.clone());
             }
         }
@@ -1153,7 +2804,12 @@ fn process_files_independent(
     Ok(())
 }
 
-fn calculate_file_hash(file: &FileInfo, quick_check: bool) -> io::Result<Option<String>> {
+/// MD5 of a file's content, or with `quick` (-C) of its samples only.
+fn calculate_file_hash(file: &FileInfo, quick: Option<&QuickSampling>) -> io::Result<Option<String>> {
+    if let Some(member) = &file.archive_member {
+        return hash_archive_member(member, file.size, quick).map(Some);
+    }
+
     let path = &file.path;
     let mut file_handle = File::open(path)?;
     let file_size = file.size;
@@ -1162,21 +2818,14 @@ fn calculate_file_hash(file: &FileInfo, quick_check: bool) -> io::Result<Option<
     // 1MB buffer reduces syscalls dramatically: 100MB file = 100 syscalls instead of 1600
     let buffer_size = 1024 * 1024; // 1 MB buffer
 
-    if quick_check && file_size > QUICKCHECK_SIZE as u64 * 2 {
+    if let Some((offsets, sample_size)) = quick.and_then(|q| Some((q.offsets(file_size)?, q.sample_size))) {
         let mut hasher = Context::new();
 
-        // Read first QUICKCHECK_SIZE bytes
-        let mut buffer = vec![0; QUICKCHECK_SIZE];
-        let bytes_read = file_handle.read(&mut buffer)?;
-        buffer.truncate(bytes_read);
-        hasher.consume(&buffer);
-
-        // Read last QUICKCHECK_SIZE bytes
-        file_handle.seek(SeekFrom::End(-(QUICKCHECK_SIZE as i64)))?;
-        let mut buffer = vec![0; QUICKCHECK_SIZE];
-        let bytes_read = file_handle.read(&mut buffer)?;
-        buffer.truncate(bytes_read);
-        hasher.consume(&buffer);
+        // Hash each sample in turn, streaming rather than buffering it whole
+        for offset in offsets {
+            file_handle.seek(SeekFrom::Start(offset))?;
+            io::copy(&mut (&mut file_handle).take(sample_size), &mut hasher)?;
+        }
 
         let result = hasher.compute();
         Ok(Some(format!("{:x}", result)))
@@ -1184,128 +2833,2583 @@ fn calculate_file_hash(file: &FileInfo, quick_check: bool) -> io::Result<Option<
         let mut hasher = Context::new();
         let mut buffer = vec![0; buffer_size]; // Use 1MB buffer
 
-        loop {
-            let bytes_read = file_handle.read(&mut buffer)?;
-            if bytes_read == 0 {
+        loop {
+            let bytes_read = file_handle.read(&mut buffer)?;
+            if bytes_read == 0 {
+                break;
+            }
+            hasher.consume(&buffer[..bytes_read]);
+        }
+
+        let result = hasher.compute();
+        Ok(Some(format!("{:x}", result)))
+    }
+}
+
+/// Compare two same-size files chunk by chunk, counting differing bytes.
+/// Never loads the full file into RAM — uses a 1MB rolling buffer matching
+/// the existing I/O strategy. With `stop_above` set, returns as soon as the
+/// count exceeds it (the returned count is then only a lower bound);
+/// with None, reads both files completely so the caller gets the exact
+/// differing byte count even when it exceeds threshold.
+fn chunked_fuzzy_compare(path_a: &Path, path_b: &Path, stop_above: Option<u64>, debug: bool) -> io::Result<u64> {
+    const CHUNK: usize = 1024 * 1024; // 1 MB, matches existing buffer size
+
+    let mut fa = File::open(path_a)?;
+    let mut fb = File::open(path_b)?;
+
+    let mut buf_a = vec![0u8; CHUNK];
+    let mut buf_b = vec![0u8; CHUNK];
+    let mut total_diff: u64 = 0;
+
+    loop {
+        let na = fa.read(&mut buf_a)?;
+        let nb = fb.read(&mut buf_b)?;
+
+        // Both files are same size (precondition from size grouping), so na == nb
+        // at every chunk. If they somehow diverge, stop — something is wrong.
+        if na == 0 && nb == 0 {
+            break;
+        }
+        if na != nb {
+            if debug {
+                eprintln!(
+                    "[FUZZY] Chunk size mismatch ({} vs {}) for {} <-> {} — aborting",
+                    na, nb, path_a.display(), path_b.display()
+                );
+            }
+            // Treat divergent read as maximum difference so the pair is rejected.
+            return Ok(u64::MAX);
+        }
+
+        let chunk_diff = buf_a[..na]
+            .iter()
+            .zip(buf_b[..nb].iter())
+            .filter(|(a, b)| a != b)
+            .count() as u64;
+        total_diff += chunk_diff;
+
+        if let Some(limit) = stop_above {
+            if total_diff > limit {
+                break;
+            }
+        }
+    }
+
+    Ok(total_diff)
+}
+
+/// Cheap pre-probe for a same-size pair: compare FUZZY_PROBE_BLOCKS small
+/// blocks spread evenly over the files. The differing bytes found are a
+/// lower bound on the total, so if they already exceed `limit` the pair can
+/// be rejected without a full read. Returns true if the pair is rejected.
+/// Files too small for sampling to pay off are never rejected.
+fn fuzzy_probe_rejects(path_a: &Path, path_b: &Path, size: u64, limit: u64) -> io::Result<bool> {
+    const FUZZY_PROBE_BLOCKS: u64 = 16;
+    const FUZZY_PROBE_BLOCK_SIZE: u64 = 4096;
+
+    if size < FUZZY_PROBE_BLOCKS * FUZZY_PROBE_BLOCK_SIZE * 16 {
+        return Ok(false);
+    }
+
+    let mut fa = File::open(path_a)?;
+    let mut fb = File::open(path_b)?;
+    let mut buf_a = vec![0u8; FUZZY_PROBE_BLOCK_SIZE as usize];
+    let mut buf_b = vec![0u8; FUZZY_PROBE_BLOCK_SIZE as usize];
+    let stride = (size - FUZZY_PROBE_BLOCK_SIZE) / (FUZZY_PROBE_BLOCKS - 1);
+    let mut sampled_diff = 0u64;
+
+    for i in 0..FUZZY_PROBE_BLOCKS {
+        let offset = i * stride;
+        fa.seek(SeekFrom::Start(offset))?;
+        fb.seek(SeekFrom::Start(offset))?;
+        fa.read_exact(&mut buf_a)?;
+        fb.read_exact(&mut buf_b)?;
+        sampled_diff += buf_a.iter().zip(buf_b.iter()).filter(|(a, b)| a != b).count() as u64;
+        if sampled_diff > limit {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Fuzzy stage shared by every find_duplicates path. Appends to `duplicates`:
+///   -Z -t/-T: byte-level comparison of the same-size candidate groups that
+///       produced no exact match (candidates come from `groups` minus exact hits).
+///   --text-normalize: normalized-text hashing of every still unmatched
+///       text file, regardless of size.
+///   --format-aware: payload matching over every scanned file that has not
+///       been matched yet, regardless of size.
+///   -Z --similarity: content-defined chunking over every still unmatched
+///       file, pairing files whose sizes are close enough to reach the score.
+///   --perceptual image: perceptual hashes of every still unmatched image.
+///   --perceptual audio: chroma fingerprints of every still unmatched track.
+fn run_fuzzy_stage(
+    duplicates: &mut Vec<DuplicateGroup>,
+    groups: &[Vec<FileInfo>],
+    all_files: &[FileInfo],
+    options: &CompareOptions,
+) -> io::Result<()> {
+    // Archive members can be hashed but not opened by path: only the exact
+    // stage sees them.
+    let groups: &[Vec<FileInfo>] = &groups
+        .iter()
+        .map(|g| g.iter().filter(|f| f.archive_member.is_none()).cloned().collect())
+        .collect::<Vec<_>>();
+    let all_files: &[FileInfo] = &all_files
+        .iter()
+        .filter(|f| f.archive_member.is_none())
+        .cloned()
+        .collect::<Vec<_>>();
+
+    // Collect the paths that are already covered by exact matches so we
+    // don't double-report them.
+    let mut matched_paths: HashSet<PathBuf> = duplicates
+        .iter()
+        .flat_map(|g| g.files_by_folder.iter().flat_map(|v| v.iter().cloned()))
+        .collect();
+
+    if options.fuzzy && (options.tolerance.is_some() || options.tolerance_pct.is_some()) {
+        // Build candidate groups: same-size, cross-folder (or any with -b/-1),
+        // not already exact.
+        let fuzzy_candidates: Vec<Vec<FileInfo>> = groups
+            .iter()
+            .map(|group| {
+                group
+                    .iter()
+                    .filter(|f| !matched_paths.contains(&f.path))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .filter(|g| g.len() > 1 && (options.intra_folder || has_files_from_both_folders(g)))
+            .collect();
+
+        if !fuzzy_candidates.is_empty() {
+            println!("Running fuzzy comparison on {} candidate groups...", fuzzy_candidates.len());
+            let fuzzy_results = fuzzy_compare_groups(&fuzzy_candidates, options)?;
+            println!("Fuzzy comparison found {} near-duplicate groups", fuzzy_results.len());
+            matched_paths.extend(
+                fuzzy_results.iter().flat_map(|g| g.files_by_folder.iter().flat_map(|v| v.iter().cloned()))
+            );
+            duplicates.extend(fuzzy_results);
+        }
+    }
+
+    if options.text_normalize {
+        let candidates: Vec<&FileInfo> = all_files
+            .iter()
+            .filter(|f| !matched_paths.contains(&f.path))
+            .collect();
+        println!("Running text-normalized comparison on {} files...", candidates.len());
+        let text_results = text_normalized_compare_files(&candidates, options);
+        println!("Text-normalized comparison found {} groups", text_results.len());
+        matched_paths.extend(
+            text_results.iter().flat_map(|g| g.files_by_folder.iter().flat_map(|v| v.iter().cloned()))
+        );
+        duplicates.extend(text_results);
+    }
+
+    if options.format_aware {
+        let candidates: Vec<&FileInfo> = all_files
+            .iter()
+            .filter(|f| !matched_paths.contains(&f.path))
+            .collect();
+        println!("Running format-aware comparison on {} files...", candidates.len());
+        let payload_results = payload_compare_files(&candidates, options);
+        println!("Format-aware comparison found {} near-duplicate groups", payload_results.len());
+        matched_paths.extend(
+            payload_results.iter().flat_map(|g| g.files_by_folder.iter().flat_map(|v| v.iter().cloned()))
+        );
+        duplicates.extend(payload_results);
+    }
+
+    if let (true, Some(min_similarity)) = (options.fuzzy, options.similarity) {
+        let candidates: Vec<&FileInfo> = all_files
+            .iter()
+            .filter(|f| !matched_paths.contains(&f.path))
+            .collect();
+        println!("Running shift-tolerant comparison on {} files...", candidates.len());
+        let similar_results = similarity_compare_files(&candidates, min_similarity, options);
+        println!("Shift-tolerant comparison found {} near-duplicate groups", similar_results.len());
+        matched_paths.extend(
+            similar_results.iter().flat_map(|g| g.files_by_folder.iter().flat_map(|v| v.iter().cloned()))
+        );
+        duplicates.extend(similar_results);
+    }
+
+    if options.perceptual_image {
+        let candidates: Vec<FileInfo> = all_files
+            .iter()
+            .filter(|f| !matched_paths.contains(&f.path))
+            .cloned()
+            .collect();
+        println!("Running perceptual image comparison on {} files...", candidates.len());
+        let perceptual_results = perceptual_image_compare_files(candidates, options);
+        println!("Perceptual image comparison found {} near-duplicate groups", perceptual_results.len());
+        matched_paths.extend(
+            perceptual_results.iter().flat_map(|g| g.files_by_folder.iter().flat_map(|v| v.iter().cloned()))
+        );
+        duplicates.extend(perceptual_results);
+    }
+
+    if options.perceptual_audio {
+        let candidates: Vec<FileInfo> = all_files
+            .iter()
+            .filter(|f| !matched_paths.contains(&f.path) && is_audio_file(&f.path))
+            .cloned()
+            .collect();
+        println!("Running audio fingerprint comparison on {} files...", candidates.len());
+        let audio_results = audio_compare_files(candidates, options);
+        println!("Audio fingerprint comparison found {} near-duplicate groups", audio_results.len());
+        duplicates.extend(audio_results);
+    }
+
+    Ok(())
+}
+
+/// Content-defined chunk boundaries: a Gear rolling hash cuts a chunk where
+/// its top CDC_MASK_BITS bits are zero (≈8 KB average), bounded by the min
+/// and max sizes. Because boundaries depend only on nearby content, bytes
+/// inserted or removed early in a file only change the chunks around the
+/// edit, and everything after it lines up again.
+const CDC_MIN_CHUNK: usize = 2 * 1024;
+const CDC_MAX_CHUNK: usize = 64 * 1024;
+const CDC_MASK_BITS: u32 = 13;
+/// --similarity below this warns: the size window (files up to 3× apart at
+/// 50%) no longer narrows the pairs much.
+const SIMILARITY_WARN_BELOW: f64 = 50.0;
+
+/// 256 pseudo-random values for the Gear hash (splitmix64, fixed seed, so
+/// chunking is identical across runs and machines).
+const GEAR_TABLE: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// (chunk hash, chunk length) pairs of one file, sorted by hash for merging.
+type CdcFingerprint = Vec<(u64, u32)>;
+
+/// Split a file into content-defined chunks and return its fingerprint.
+/// Chunk hashes are 64-bit FNV-1a.
+fn cdc_fingerprint(path: &Path) -> io::Result<CdcFingerprint> {
+    const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0u8; 1024 * 1024];
+    let mut chunks = Vec::new();
+    let mut gear = 0u64;
+    let mut fnv = FNV_OFFSET;
+    let mut len = 0usize;
+
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        for &byte in &buffer[..n] {
+            gear = (gear << 1).wrapping_add(GEAR_TABLE[byte as usize]);
+            fnv = (fnv ^ byte as u64).wrapping_mul(FNV_PRIME);
+            len += 1;
+            if (len >= CDC_MIN_CHUNK && gear >> (64 - CDC_MASK_BITS) == 0) || len >= CDC_MAX_CHUNK {
+                chunks.push((fnv, len as u32));
+                gear = 0;
+                fnv = FNV_OFFSET;
+                len = 0;
+            }
+        }
+    }
+    if len > 0 {
+        chunks.push((fnv, len as u32));
+    }
+
+    chunks.sort_unstable();
+    Ok(chunks)
+}
+
+/// Bytes covered by chunks present in both (sorted) fingerprints, counting
+/// repeated chunks as many times as they occur in both.
+fn cdc_shared_bytes(a: &[(u64, u32)], b: &[(u64, u32)]) -> u64 {
+    let (mut i, mut j, mut shared) = (0, 0, 0u64);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Equal => {
+                shared += a[i].1 as u64;
+                i += 1;
+                j += 1;
+            }
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+        }
+    }
+    shared
+}
+
+/// -Z --similarity: cluster files whose content-defined chunks overlap by
+/// at least `min_similarity` percent, where
+/// similarity = 2 × shared bytes / (size_a + size_b).
+/// Since shared bytes can't exceed the smaller file, only files within the
+/// size window min/max ≥ P / (200 − P) can reach the score, so candidates
+/// are the neighbours of each file in size order inside that window.
+/// Cross-folder pairs only, unless -b/-1 asked for intra-folder results.
+fn similarity_compare_files(files: &[&FileInfo], min_similarity: f64, options: &CompareOptions) -> Vec<DuplicateGroup> {
+    let mut sorted: Vec<&FileInfo> = files.iter().copied().filter(|f| f.size > 0).collect();
+    sorted.sort_by_key(|f| f.size);
+    let window_ratio = min_similarity / (200.0 - min_similarity);
+
+    // A fingerprint is computed when its file first enters the size window
+    // and dropped once the scan has moved past it, so only the files in the
+    // current window are held. Clustering recomputes the few it still needs.
+    // Some(None) marks a file that could not be read.
+    let in_window = |i: usize, j: usize| {
+        let (small, large) = (sorted[i].size.min(sorted[j].size), sorted[i].size.max(sorted[j].size));
+        small as f64 >= large as f64 * window_ratio
+    };
+    let mut fingerprints: Vec<Option<Option<CdcFingerprint>>> = vec![None; sorted.len()];
+    let distance = |fingerprints: &mut Vec<Option<Option<CdcFingerprint>>>, i: usize, j: usize| -> Option<u64> {
+        if !in_window(i, j) {
+            return None; // the smaller file cannot cover enough of the larger one
+        }
+        for k in [i, j] {
+            if fingerprints[k].is_none() {
+                fingerprints[k] = Some(match cdc_fingerprint(&sorted[k].path) {
+                    Ok(fp) => Some(fp),
+                    Err(e) => {
+                        if options.debug {
+                            eprintln!("[SIMILAR] Error reading {}: {}", sorted[k].path.display(), e);
+                        }
+                        None
+                    }
+                });
+            }
+        }
+        let (fp_a, fp_b) = match (&fingerprints[i], &fingerprints[j]) {
+            (Some(Some(a)), Some(Some(b))) => (a, b),
+            _ => return None,
+        };
+        let (a, b) = (sorted[i], sorted[j]);
+        let shared = cdc_shared_bytes(fp_a, fp_b);
+        let score = 200.0 * shared as f64 / (a.size + b.size) as f64;
+        if score < min_similarity {
+            if options.debug {
+                println!(
+                    "[SIMILAR EXCEED] {:.2}% similar (<{}%) — skipped\n  {} <-> {}",
+                    score, min_similarity, a.path.display(), b.path.display()
+                );
+            }
+            return None;
+        }
+        if options.debug {
+            println!(
+                "[SIMILAR MATCH] {:.2}% similar (≥{}%)\n  {} <-> {}",
+                score, min_similarity, a.path.display(), b.path.display()
+            );
+        }
+        Some(a.size + b.size - 2 * shared)
+    };
+
+    let mut edges = Vec::new();
+    for i in 0..sorted.len() {
+        for j in i + 1..sorted.len() {
+            if !in_window(i, j) {
+                break; // sorted by size: every later file is further away
+            }
+            if sorted[i].folder_index == sorted[j].folder_index && !options.intra_folder {
+                continue;
+            }
+            if let Some(d) = distance(&mut fingerprints, i, j) {
+                edges.push((d, i, j));
+            }
+        }
+        // Every later pair starts further right: file i has left the window
+        fingerprints[i] = None;
+    }
+
+    cluster_near_duplicates(edges, |i, j| distance(&mut fingerprints, i, j))
+        .into_iter()
+        .map(|cluster| {
+            let (a, b) = (sorted[cluster.widest.0], sorted[cluster.widest.1]);
+            let score = 100.0 - 100.0 * cluster.diameter as f64 / (a.size + b.size) as f64;
+            let mut files_by_folder = vec![Vec::new(), Vec::new()];
+            for &m in &cluster.members {
+                files_by_folder[sorted[m].folder_index].push(sorted[m].path.clone());
+            }
+            DuplicateGroup {
+                files_by_folder,
+                size: cluster.members.iter().map(|&m| sorted[m].size).min().unwrap_or(0),
+                match_kind: MatchKind::Fuzzy {
+                    bytes_differing: cluster.diameter,
+                    reason: Some(format!("{:.2}% similar", score)),
+                },
+            }
+        })
+        .collect()
+}
+
+/// Perceptual hashes of a decoded image. Both are 64-bit and compared by
+/// Hamming distance; a pair matches only if both are close.
+///   dHash: brightness gradients between neighbouring pixels of a 9×8 thumbnail.
+///   pHash: signs of the 8×8 lowest-frequency DCT coefficients of a 32×32
+///          thumbnail relative to their median.
+#[derive(Debug, Clone, Copy)]
+struct ImageHash {
+    dhash: u64,
+    phash: u64,
+}
+
+impl ImageHash {
+    fn distance(&self, other: &ImageHash) -> u32 {
+        (self.dhash ^ other.dhash).count_ones().max((self.phash ^ other.phash).count_ones())
+    }
+}
+
+/// Decode an image and compute its perceptual hashes. Returns None for files
+/// that are not in a supported image format (detected from the content, not
+/// the extension).
+fn image_hash(path: &Path) -> io::Result<Option<ImageHash>> {
+    use image::imageops::FilterType;
+
+    let reader = image::ImageReader::open(path)?.with_guessed_format()?;
+    if reader.format().is_none() {
+        return Ok(None);
+    }
+    let gray = reader
+        .decode()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        .grayscale();
+
+    let thumb = gray.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut dhash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            dhash <<= 1;
+            if thumb.get_pixel(x, y)[0] < thumb.get_pixel(x + 1, y)[0] {
+                dhash |= 1;
+            }
+        }
+    }
+
+    let thumb = gray.resize_exact(32, 32, FilterType::Triangle).to_luma8();
+    let cosines: Vec<[f64; 32]> = (0..8)
+        .map(|u| {
+            let mut row = [0.0; 32];
+            for (x, c) in row.iter_mut().enumerate() {
+                *c = ((2 * x + 1) as f64 * u as f64 * std::f64::consts::PI / 64.0).cos();
+            }
+            row
+        })
+        .collect();
+    let mut coefficients = [0.0f64; 64];
+    for u in 0..8 {
+        for v in 0..8 {
+            let mut sum = 0.0;
+            for y in 0..32 {
+                for x in 0..32 {
+                    sum += thumb.get_pixel(x as u32, y as u32)[0] as f64 * cosines[u][x] * cosines[v][y];
+                }
+            }
+            coefficients[u * 8 + v] = sum;
+        }
+    }
+    // The DC term is overall brightness; leave it out of the median
+    let mut ac: Vec<f64> = coefficients[1..].to_vec();
+    ac.sort_by(|a, b| a.total_cmp(b));
+    let median = ac[ac.len() / 2];
+    let phash = coefficients.iter().fold(0u64, |acc, c| (acc << 1) | (*c > median) as u64);
+
+    Ok(Some(ImageHash { dhash, phash }))
+}
+
+/// --perceptual image: hash every decodable image (on worker threads with
+/// -A/-E, split like the other parallel stages) and cluster images whose
+/// hashes are within --perceptual-distance. Cross-folder pairs only, unless
+/// -b/-1 asked for intra-folder results. Sizes don't matter, so resized,
+/// recompressed and converted copies are found.
+fn perceptual_image_compare_files(files: Vec<FileInfo>, options: &CompareOptions) -> Vec<DuplicateGroup> {
+    let hash_all = |files: Vec<FileInfo>, debug: bool| -> Vec<(FileInfo, ImageHash)> {
+        files
+            .into_iter()
+            .filter_map(|file| match catch_decoder_panic(|| image_hash(&file.path)) {
+                Ok(Some(hash)) => Some((file, hash)),
+                Ok(None) => None,
+                Err(e) => {
+                    if debug {
+                        eprintln!("[PERCEPTUAL] Error decoding {}: {}", file.path.display(), e);
+                    }
+                    None
+                }
+            })
+            .collect()
+    };
+
+    let hashed: Vec<(FileInfo, ImageHash)> = if options.async_compare || options.enhanced_async {
+        let chunks = split_into_chunks(files, num_cpus::get(), options.hdd_optimized);
+        let handles: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| {
+                let debug = options.debug;
+                thread::spawn(move || hash_all(chunk, debug))
+            })
+            .collect();
+        handles.into_iter().flat_map(|h| h.join().unwrap_or_default()).collect()
+    } else {
+        hash_all(files, options.debug)
+    };
+
+    let max_distance = options.perceptual_distance;
+    let distance = |i: usize, j: usize| -> Option<u64> {
+        let d = hashed[i].1.distance(&hashed[j].1);
+        if options.debug {
+            println!(
+                "[PERCEPTUAL {}] distance {} (max {})\n  {} <-> {}",
+                if d <= max_distance { "MATCH" } else { "EXCEED" },
+                d, max_distance, hashed[i].0.path.display(), hashed[j].0.path.display()
+            );
+        }
+        (d <= max_distance).then_some(d as u64)
+    };
+
+    // Hashes are tiny, so comparing every pair is cheap next to decoding
+    let mut edges = Vec::new();
+    for i in 0..hashed.len() {
+        for j in i + 1..hashed.len() {
+            if hashed[i].0.folder_index == hashed[j].0.folder_index && !options.intra_folder {
+                continue;
+            }
+            if let Some(d) = distance(i, j) {
+                edges.push((d, i, j));
+            }
+        }
+    }
+
+    cluster_near_duplicates(edges, distance)
+        .into_iter()
+        .map(|cluster| {
+            let mut files_by_folder = vec![Vec::new(), Vec::new()];
+            for &m in &cluster.members {
+                files_by_folder[hashed[m].0.folder_index].push(hashed[m].0.path.clone());
+            }
+            DuplicateGroup {
+                files_by_folder,
+                size: cluster.members.iter().map(|&m| hashed[m].0.size).min().unwrap_or(0),
+                match_kind: MatchKind::Perceptual { distance: cluster.diameter as u32 },
+            }
+        })
+        .collect()
+}
+
+/// Run a third-party decoder on one file, turning a panic inside it (a
+/// corrupt file tripping an assertion) into an error for that file only.
+fn catch_decoder_panic<T>(decode: impl FnOnce() -> io::Result<Option<T>>) -> io::Result<Option<T>> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(decode))
+        .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::InvalidData, "decoder panicked")))
+}
+
+/// --perceptual audio: only the first this many seconds are fingerprinted.
+const AUDIO_FINGERPRINT_SECONDS: usize = 120;
+/// Decoded audio is mixed to mono and resampled to exactly this rate, so
+/// frames cover the same time whatever the source rate.
+const AUDIO_TARGET_RATE: u32 = 11025;
+/// FFT window (≈0.37 s at the target rate) and hop between windows.
+const AUDIO_FRAME: usize = 4096;
+const AUDIO_HOP: usize = AUDIO_FRAME / 3;
+/// Largest alignment shift tried (in hops, ≈2 s), covering encoder delay
+/// and padding differences between MP3, AAC and lossless copies.
+const AUDIO_MAX_SHIFT: isize = 16;
+/// Bits used per fingerprint frame: 12 pitch-gradient + 12 temporal bits.
+const AUDIO_BITS: u32 = 24;
+
+/// Extensions tried by --perceptual audio. Probing every file would make the
+/// decoder scan non-audio data for frame syncs.
+fn is_audio_file(path: &Path) -> bool {
+    const AUDIO_EXTENSIONS: &[&str] = &[
+        "mp3", "flac", "m4a", "m4b", "mp4", "aac", "alac", "wav", "wave", "aif", "aiff", "caf", "ogg", "oga", "mka", "webm",
+    ];
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| AUDIO_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+/// Chromaprint-style fingerprint of a track: per FFT frame, a 12-bin chroma
+/// vector (energy per pitch class) is reduced to AUDIO_BITS bits — whether
+/// each pitch class is louder than the next one, and whether it got louder
+/// than two frames before. Pitch content survives lossy encoding, so
+/// FLAC, MP3 and AAC copies of a track end up with mostly the same bits.
+struct AudioFingerprint {
+    frames: Vec<u32>,
+    /// Full track length in seconds, when the container states it.
+    duration: Option<f64>,
+}
+
+/// Mono samples of the start of a track, as returned by decode_audio_mono.
+struct DecodedAudio {
+    samples: Vec<f32>,
+    rate: u32,
+    /// Full track length in seconds, when the container states it.
+    duration: Option<f64>,
+}
+
+/// Decode up to AUDIO_FINGERPRINT_SECONDS of a track to mono, resampled to
+/// AUDIO_TARGET_RATE. None if the file is not a supported audio format.
+fn decode_audio_mono(path: &Path) -> io::Result<Option<DecodedAudio>> {
+    use symphonia::core::audio::SampleBuffer;
+    use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+    use symphonia::core::errors::Error as AudioError;
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+
+    let to_io = |e: AudioError| match e {
+        AudioError::IoError(e) => e,
+        other => io::Error::new(io::ErrorKind::InvalidData, other),
+    };
+
+    let stream = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+    let probed = match symphonia::default::get_probe().format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default()) {
+        Ok(p) => p,
+        Err(AudioError::Unsupported(_)) => return Ok(None),
+        Err(e) => return Err(to_io(e)),
+    };
+    let mut format = probed.format;
+    let track = match format.tracks().iter().find(|t| t.codec_params.codec != CODEC_TYPE_NULL) {
+        Some(t) => t,
+        None => return Ok(None),
+    };
+    let track_id = track.id;
+    let rate = match track.codec_params.sample_rate {
+        Some(r) if r > 0 => r,
+        _ => return Ok(None),
+    };
+    let duration = track.codec_params.n_frames.map(|n| n as f64 / rate as f64);
+    let mut decoder = match symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default()) {
+        Ok(d) => d,
+        Err(AudioError::Unsupported(_)) => return Ok(None),
+        Err(e) => return Err(to_io(e)),
+    };
+
+    // Average runs of `factor` samples first (a cheap low-pass against
+    // aliasing), then resample_linear takes the rest of the way.
+    let factor = (rate / AUDIO_TARGET_RATE).max(1) as usize;
+    let averaged_rate = rate as f64 / factor as f64;
+    let wanted = (AUDIO_FINGERPRINT_SECONDS as f64 * averaged_rate).ceil() as usize;
+    let mut samples: Vec<f32> = Vec::with_capacity(wanted);
+    let (mut acc, mut acc_count) = (0.0f32, 0usize);
+
+    while samples.len() < wanted {
+        let packet = match format.next_packet() {
+            Ok(p) => p,
+            Err(AudioError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(to_io(e)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(d) => d,
+            Err(AudioError::DecodeError(_)) => continue, // skip a corrupt packet
+            Err(e) => return Err(to_io(e)),
+        };
+        let spec = *decoded.spec();
+        let channels = spec.channels.count().max(1);
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        for frame in buffer.samples().chunks(channels) {
+            acc += frame.iter().sum::<f32>() / channels as f32;
+            acc_count += 1;
+            if acc_count == factor {
+                samples.push(acc / factor as f32);
+                acc = 0.0;
+                acc_count = 0;
+            }
+        }
+    }
+    samples.truncate(wanted);
+    let samples = resample_linear(&samples, averaged_rate, AUDIO_TARGET_RATE as f64);
+
+    Ok(Some(DecodedAudio { samples, rate: AUDIO_TARGET_RATE, duration }))
+}
+
+/// Resample by linear interpolation between neighbouring samples.
+fn resample_linear(samples: &[f32], from_rate: f64, to_rate: f64) -> Vec<f32> {
+    if samples.is_empty() || from_rate == to_rate {
+        return samples.to_vec();
+    }
+    let step = from_rate / to_rate;
+    let count = ((samples.len() - 1) as f64 / step) as usize + 1;
+    (0..count)
+        .map(|i| {
+            let pos = i as f64 * step;
+            let index = pos as usize;
+            let frac = (pos - index as f64) as f32;
+            let next = samples.get(index + 1).copied().unwrap_or(samples[index]);
+            samples[index] + (next - samples[index]) * frac
+        })
+        .collect()
+}
+
+/// In-place iterative radix-2 FFT; the length must be a power of two.
+fn fft_in_place(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * std::f64::consts::PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_re, w_im) = ((angle * k as f64).cos(), (angle * k as f64).sin());
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Decode a track and compute its AudioFingerprint. None if the file is not
+/// a supported audio format or too short to fingerprint.
+fn audio_fingerprint(path: &Path) -> io::Result<Option<AudioFingerprint>> {
+    let DecodedAudio { samples, rate, duration } = match decode_audio_mono(path)? {
+        Some(decoded) => decoded,
+        None => return Ok(None),
+    };
+    if samples.len() < AUDIO_FRAME {
+        return Ok(None);
+    }
+
+    // Pitch class of every FFT bin in the musical range (28 Hz – 3.5 kHz)
+    let pitch_class: Vec<Option<usize>> = (0..AUDIO_FRAME / 2)
+        .map(|k| {
+            let freq = k as f64 * rate as f64 / AUDIO_FRAME as f64;
+            (28.0..=3520.0).contains(&freq)
+                .then(|| ((12.0 * (freq / 440.0).log2() + 69.0).round() as i64).rem_euclid(12) as usize)
+        })
+        .collect();
+    let window: Vec<f64> = (0..AUDIO_FRAME)
+        .map(|i| 0.5 - 0.5 * (2.0 * std::f64::consts::PI * i as f64 / AUDIO_FRAME as f64).cos())
+        .collect();
+
+    let mut chroma: Vec<[f64; 12]> = Vec::new();
+    let mut re = vec![0.0; AUDIO_FRAME];
+    let mut im = vec![0.0; AUDIO_FRAME];
+    for start in (0..=samples.len() - AUDIO_FRAME).step_by(AUDIO_HOP) {
+        for i in 0..AUDIO_FRAME {
+            re[i] = samples[start + i] as f64 * window[i];
+            im[i] = 0.0;
+        }
+        fft_in_place(&mut re, &mut im);
+        let mut bins = [0.0f64; 12];
+        for (k, pc) in pitch_class.iter().enumerate() {
+            if let Some(pc) = pc {
+                bins[*pc] += re[k] * re[k] + im[k] * im[k];
+            }
+        }
+        let norm = bins.iter().map(|b| b * b).sum::<f64>().sqrt();
+        if norm > 0.0 {
+            bins.iter_mut().for_each(|b| *b /= norm);
+        }
+        chroma.push(bins);
+    }
+
+    // Smooth over 4 frames so the bits follow the music, not frame jitter
+    let smoothed: Vec<[f64; 12]> = (0..chroma.len().saturating_sub(3))
+        .map(|t| {
+            let mut avg = [0.0; 12];
+            for frame in &chroma[t..t + 4] {
+                for (a, c) in avg.iter_mut().zip(frame) {
+                    *a += c / 4.0;
+                }
+            }
+            avg
+        })
+        .collect();
+    let frames = (2..smoothed.len())
+        .map(|t| {
+            let mut bits = 0u32;
+            for pc in 0..12 {
+                bits = (bits << 1) | (smoothed[t][pc] > smoothed[t][(pc + 1) % 12]) as u32;
+                bits = (bits << 1) | (smoothed[t][pc] > smoothed[t - 2][pc]) as u32;
+            }
+            bits
+        })
+        .collect();
+
+    Ok(Some(AudioFingerprint { frames, duration }))
+}
+
+/// Percentage of matching fingerprint bits between two tracks, at the best
+/// alignment within ±AUDIO_MAX_SHIFT frames. The compared stretch must cover
+/// most of the shorter fingerprint, so a short clip can't match a long track
+/// just by lining up with part of it.
+fn audio_fingerprint_similarity(a: &AudioFingerprint, b: &AudioFingerprint) -> f64 {
+    let shorter = a.frames.len().min(b.frames.len());
+    let mut best = 0.0f64;
+    for shift in -AUDIO_MAX_SHIFT..=AUDIO_MAX_SHIFT {
+        let (offset_a, offset_b) = if shift >= 0 { (shift as usize, 0) } else { (0, (-shift) as usize) };
+        let overlap = a.frames.len().saturating_sub(offset_a).min(b.frames.len().saturating_sub(offset_b));
+        if overlap == 0 || overlap * 10 < shorter * 9 {
+            continue;
+        }
+        let differing: u32 = (0..overlap)
+            .map(|i| (a.frames[offset_a + i] ^ b.frames[offset_b + i]).count_ones())
+            .sum();
+        let similarity = 100.0 * (1.0 - differing as f64 / (overlap as f64 * AUDIO_BITS as f64));
+        best = best.max(similarity);
+    }
+    best
+}
+
+/// --perceptual audio: fingerprint every audio file (on worker threads with
+/// -A/-E) and cluster tracks whose fingerprints agree on at least
+/// --audio-similarity percent of their bits. Tracks whose stated durations
+/// differ by more than 5% (+1 s) are never paired. Cross-folder pairs only,
+/// unless -b/-1 asked for intra-folder results. Reported as `MatchKind::Fuzzy`
+/// with the similarity as reason, so they follow the `differ/` flow.
+fn audio_compare_files(files: Vec<FileInfo>, options: &CompareOptions) -> Vec<DuplicateGroup> {
+    let fingerprint_all = |files: Vec<FileInfo>, debug: bool| -> Vec<(FileInfo, AudioFingerprint)> {
+        files
+            .into_iter()
+            .filter_map(|file| match catch_decoder_panic(|| audio_fingerprint(&file.path)) {
+                Ok(Some(fp)) => Some((file, fp)),
+                Ok(None) => None,
+                Err(e) => {
+                    if debug {
+                        eprintln!("[AUDIO] Error decoding {}: {}", file.path.display(), e);
+                    }
+                    None
+                }
+            })
+            .collect()
+    };
+
+    let fingerprinted: Vec<(FileInfo, AudioFingerprint)> = if options.async_compare || options.enhanced_async {
+        let chunks = split_into_chunks(files, num_cpus::get(), options.hdd_optimized);
+        let handles: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| {
+                let debug = options.debug;
+                thread::spawn(move || fingerprint_all(chunk, debug))
+            })
+            .collect();
+        handles.into_iter().flat_map(|h| h.join().unwrap_or_default()).collect()
+    } else {
+        fingerprint_all(files, options.debug)
+    };
+
+    let min_similarity = options.audio_similarity;
+    // Cluster distance: missing similarity in hundredths of a percent
+    let distance = |i: usize, j: usize| -> Option<u64> {
+        let (a, b) = (&fingerprinted[i], &fingerprinted[j]);
+        if let (Some(da), Some(db)) = (a.1.duration, b.1.duration) {
+            if (da - db).abs() > da.max(db) * 0.05 + 1.0 {
+                return None;
+            }
+        }
+        let similarity = audio_fingerprint_similarity(&a.1, &b.1);
+        if options.debug {
+            println!(
+                "[AUDIO {}] {:.1}% similar (min {}%)\n  {} <-> {}",
+                if similarity >= min_similarity { "MATCH" } else { "EXCEED" },
+                similarity, min_similarity, a.0.path.display(), b.0.path.display()
+            );
+        }
+        (similarity >= min_similarity).then(|| ((100.0 - similarity) * 100.0).round() as u64)
+    };
+
+    let mut edges = Vec::new();
+    for i in 0..fingerprinted.len() {
+        for j in i + 1..fingerprinted.len() {
+            if fingerprinted[i].0.folder_index == fingerprinted[j].0.folder_index && !options.intra_folder {
+                continue;
+            }
+            if let Some(d) = distance(i, j) {
+                edges.push((d, i, j));
+            }
+        }
+    }
+
+    cluster_near_duplicates(edges, distance)
+        .into_iter()
+        .map(|cluster| {
+            let mut files_by_folder = vec![Vec::new(), Vec::new()];
+            for &m in &cluster.members {
+                files_by_folder[fingerprinted[m].0.folder_index].push(fingerprinted[m].0.path.clone());
+            }
+            let sizes = cluster.members.iter().map(|&m| fingerprinted[m].0.size);
+            let (min_size, max_size) = (sizes.clone().min().unwrap_or(0), sizes.max().unwrap_or(0));
+            DuplicateGroup {
+                files_by_folder,
+                size: min_size,
+                match_kind: MatchKind::Fuzzy {
+                    bytes_differing: max_size - min_size,
+                    reason: Some(format!("audio {:.1}% similar", 100.0 - cluster.diameter as f64 / 100.0)),
+                },
+            }
+        })
+        .collect()
+}
+
+/// A set of near-duplicates found by cluster_near_duplicates. `members` are
+/// indices into the caller's file list; `diameter` is the largest distance
+/// between any two members, found between the pair `widest`.
+struct FuzzyCluster {
+    members: Vec<usize>,
+    diameter: u64,
+    widest: (usize, usize),
+}
+
+/// Turn pairwise near-duplicate matches into clusters, so five near-identical
+/// copies are reported once instead of as a web of pairs.
+///
+/// `edges` are matching pairs (distance, a, b). They are merged with a
+/// union-find, closest pairs first, but two sets are only joined if every
+/// member of one is within the threshold of every member of the other
+/// (diameter check), so A≈B and B≈C never puts A and C together unless
+/// A≈C too. `distance` returns the distance of a pair, or None if it is
+/// over the threshold; it is only called for pairs not already in `edges`.
+fn cluster_near_duplicates(
+    mut edges: Vec<(u64, usize, usize)>,
+    mut distance: impl FnMut(usize, usize) -> Option<u64>,
+) -> Vec<FuzzyCluster> {
+    edges.sort_unstable();
+    let mut known: HashMap<(usize, usize), Option<u64>> =
+        edges.iter().map(|&(d, a, b)| ((a.min(b), a.max(b)), Some(d))).collect();
+    let mut parent: HashMap<usize, usize> = HashMap::new();
+    let mut clusters: HashMap<usize, FuzzyCluster> = HashMap::new();
+
+    fn find(parent: &mut HashMap<usize, usize>, x: usize) -> usize {
+        let p = *parent.entry(x).or_insert(x);
+        if p == x {
+            return x;
+        }
+        let root = find(parent, p);
+        parent.insert(x, root);
+        root
+    }
+
+    for &(d, a, b) in &edges {
+        let (ra, rb) = (find(&mut parent, a), find(&mut parent, b));
+        if ra == rb {
+            continue;
+        }
+        let members_a = clusters.get(&ra).map_or_else(|| vec![a], |c| c.members.clone());
+        let members_b = clusters.get(&rb).map_or_else(|| vec![b], |c| c.members.clone());
+
+        // Diameter check: every cross pair must be within the threshold
+        let mut widest = (d, (a, b));
+        let mut fits = true;
+        'check: for &x in &members_a {
+            for &y in &members_b {
+                let pair_distance = *known
+                    .entry((x.min(y), x.max(y)))
+                    .or_insert_with(|| distance(x, y));
+                match pair_distance {
+                    Some(pd) if pd > widest.0 => widest = (pd, (x, y)),
+                    Some(_) => {}
+                    None => {
+                        fits = false;
+                        break 'check;
+                    }
+                }
+            }
+        }
+        if !fits {
+            continue;
+        }
+
+        for old in [clusters.get(&ra), clusters.get(&rb)].into_iter().flatten() {
+            if old.diameter > widest.0 {
+                widest = (old.diameter, old.widest);
+            }
+        }
+        clusters.remove(&ra);
+        clusters.remove(&rb);
+        parent.insert(rb, ra);
+        let mut members = members_a;
+        members.extend(members_b);
+        clusters.insert(ra, FuzzyCluster { members, diameter: widest.0, widest: widest.1 });
+    }
+
+    let mut result: Vec<FuzzyCluster> = clusters.into_values().collect();
+    for cluster in &mut result {
+        cluster.members.sort_unstable();
+    }
+    result.sort_by_key(|c| c.members[0]);
+    result
+}
+
+/// --text-normalize: hash every text file's normalized content (see
+/// text_normalized_hash) and group files whose hashes agree. Binary files
+/// are ignored. Only groups spanning both folders are reported, unless
+/// -b/-1 asked for intra-folder results.
+fn text_normalized_compare_files(files: &[&FileInfo], options: &CompareOptions) -> Vec<DuplicateGroup> {
+    let mut by_hash: HashMap<String, Vec<&FileInfo>> = HashMap::new();
+
+    for file in files.iter().filter(|f| f.size > 0) {
+        match text_normalized_hash(&file.path) {
+            Ok(Some(hash)) => by_hash.entry(hash).or_default().push(file),
+            Ok(None) => {}
+            Err(e) => {
+                if options.debug {
+                    eprintln!("[TEXT] Error reading {}: {}", file.path.display(), e);
+                }
+            }
+        }
+    }
+
+    let mut results = Vec::new();
+    for group in by_hash.into_values() {
+        let owned: Vec<FileInfo> = group.iter().map(|f| (*f).clone()).collect();
+        if owned.len() < 2 || (!options.intra_folder && !has_files_from_both_folders(&owned)) {
+            continue;
+        }
+        if options.debug {
+            println!("[TEXT MATCH] identical after normalization ({} files)", owned.len());
+            for f in &owned {
+                println!("  {}", f.path.display());
+            }
+        }
+        let mut files_by_folder = vec![Vec::new(), Vec::new()];
+        for f in &owned {
+            files_by_folder[f.folder_index].push(f.path.clone());
+        }
+        results.push(DuplicateGroup {
+            files_by_folder,
+            size: owned.iter().map(|f| f.size).min().unwrap_or(0),
+            match_kind: MatchKind::TextNormalized,
+        });
+    }
+
+    results
+}
+
+/// Hash a text file with everything that differs between Windows and Unix
+/// copies of the same document normalized away: a UTF-8 BOM is dropped,
+/// CRLF and lone CR become LF, spaces and tabs at the end of each line are
+/// removed, and so are newlines at the end of the file. Returns None if the
+/// file does not look like text (a NUL byte or invalid UTF-8 in its first
+/// TEXT_PROBE_LEN bytes). Streams the file; never holds it in memory.
+fn text_normalized_hash(path: &Path) -> io::Result<Option<String>> {
+    const TEXT_PROBE_LEN: usize = 8192;
+    const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0u8; 1024 * 1024];
+    let mut n = read_full(&mut file, &mut buffer)?;
+
+    let skip = if buffer[..n].starts_with(BOM) { BOM.len() } else { 0 };
+    let probe = &buffer[skip..n.min(skip + TEXT_PROBE_LEN)];
+    if probe.contains(&0) {
+        return Ok(None);
+    }
+    if let Err(e) = std::str::from_utf8(probe) {
+        // A character cut off by the probe length is fine; anything else isn't
+        if e.error_len().is_some() {
+            return Ok(None);
+        }
+    }
+
+    let mut hasher = Context::new();
+    let mut out = Vec::with_capacity(buffer.len());
+    let mut pending_blanks: Vec<u8> = Vec::new(); // spaces/tabs not yet known to be trailing
+    let mut pending_newlines = 0usize; // newlines not yet known to be trailing
+    let mut after_cr = false;
+    let mut start = skip;
+
+    while n > 0 {
+        for &b in &buffer[start..n] {
+            match b {
+                b'\n' if after_cr => after_cr = false, // second half of CRLF
+                b'\r' | b'\n' => {
+                    after_cr = b == b'\r';
+                    pending_blanks.clear();
+                    pending_newlines += 1;
+                }
+                b' ' | b'\t' => {
+                    after_cr = false;
+                    pending_blanks.push(b);
+                }
+                _ => {
+                    after_cr = false;
+                    out.extend(std::iter::repeat_n(b'\n', pending_newlines));
+                    out.append(&mut pending_blanks);
+                    pending_newlines = 0;
+                    out.push(b);
+                }
+            }
+        }
+        hasher.consume(&out);
+        out.clear();
+        start = 0;
+        n = read_full(&mut file, &mut buffer)?;
+    }
+
+    Ok(Some(format!("{:x}", hasher.compute())))
+}
+
+/// Fuzzy Mode 2: hash each file's canonical payload (metadata skipped) and
+/// group files whose payload hashes agree. Files in no supported format are
+/// ignored. Only groups spanning both folders are reported, like -Z, unless
+/// -b/-1 asked for intra-folder results.
+fn payload_compare_files(files: &[&FileInfo], options: &CompareOptions) -> Vec<DuplicateGroup> {
+    let mut by_payload: HashMap<(&'static str, String), Vec<&FileInfo>> = HashMap::new();
+
+    for file in files {
+        match payload_hash(&file.path) {
+            Ok(Some((format, hash))) => {
+                by_payload.entry((format, hash)).or_default().push(file);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                if options.debug {
+                    eprintln!("[FORMAT] Error reading {}: {}", file.path.display(), e);
+                }
+            }
+        }
+    }
+
+    let mut results = Vec::new();
+    for ((format, _), group) in by_payload {
+        let owned: Vec<FileInfo> = group.iter().map(|f| (*f).clone()).collect();
+        if owned.len() < 2 || (!options.intra_folder && !has_files_from_both_folders(&owned)) {
+            continue;
+        }
+        let min_size = owned.iter().map(|f| f.size).min().unwrap_or(0);
+        let max_size = owned.iter().map(|f| f.size).max().unwrap_or(0);
+        if options.debug {
+            println!("[FORMAT MATCH] {} payload identical ({} files)", format, owned.len());
+            for f in &owned {
+                println!("  {}", f.path.display());
+            }
+        }
+        let mut files_by_folder = vec![Vec::new(), Vec::new()];
+        for f in &owned {
+            files_by_folder[f.folder_index].push(f.path.clone());
+        }
+        results.push(DuplicateGroup {
+            files_by_folder,
+            size: min_size,
+            match_kind: MatchKind::Fuzzy {
+                bytes_differing: max_size - min_size,
+                reason: Some(format!("{} payload identical, metadata skipped", format)),
+            },
+        });
+    }
+
+    results
+}
+
+/// A --format-aware payload extractor: recognises a format by the first
+/// bytes of the file and feeds only its canonical data region (the content,
+/// without metadata) into a hasher.
+struct PayloadExtractor {
+    name: &'static str,
+    /// Called with up to PAYLOAD_PROBE_LEN leading bytes (after any ID3v2 tag).
+    matches: fn(&[u8]) -> bool,
+    /// Called with the file and the offset the format starts at; returns
+    /// false if the file turns out not to parse.
+    extract: fn(&mut File, u64, &mut Context) -> io::Result<bool>,
+    /// Called the same way by the visual diff; appends labels for the
+    /// structures it recognises. Best effort: stops quietly at malformed data.
+    describe: fn(&mut File, u64, &mut Vec<StructureLabel>) -> io::Result<()>,
+}
+
+/// A named byte range `[start, end)` of a file, e.g. an EXIF tag's value.
+struct StructureLabel {
+    start: u64,
+    end: u64,
+    name: String,
+}
+
+const PAYLOAD_PROBE_LEN: usize = 16;
+
+/// Registry for --format-aware, probed in order. To support a new format,
+/// add an entry here.
+const PAYLOAD_EXTRACTORS: &[PayloadExtractor] = &[
+    PayloadExtractor {
+        name: "JPEG",
+        matches: |m| m.starts_with(&[0xFF, 0xD8, 0xFF]),
+        extract: jpeg_extract,
+        describe: jpeg_describe,
+    },
+    PayloadExtractor {
+        name: "PNG",
+        matches: |m| m.starts_with(b"\x89PNG\r\n\x1a\n"),
+        extract: png_extract,
+        describe: png_describe,
+    },
+    PayloadExtractor {
+        name: "MP4/MOV",
+        // ISO-BMFF / QuickTime: the first atom's type sits at offset 4
+        matches: |m| m.len() >= 8 && matches!(&m[4..8], b"ftyp" | b"moov" | b"mdat" | b"wide" | b"free" | b"skip"),
+        extract: mp4_extract,
+        describe: mp4_describe,
+    },
+    PayloadExtractor {
+        name: "FLAC",
+        matches: |m| m.starts_with(b"fLaC"),
+        extract: flac_extract,
+        describe: flac_describe,
+    },
+    PayloadExtractor {
+        name: "MP3",
+        // MPEG audio frame sync with a non-zero layer (layer 00 is AAC ADTS)
+        matches: |m| m.len() >= 2 && m[0] == 0xFF && (m[1] & 0xE0) == 0xE0 && (m[1] & 0x06) != 0,
+        extract: mp3_extract,
+        describe: mp3_describe,
+    },
+    PayloadExtractor {
+        name: "PDF",
+        matches: |m| m.starts_with(b"%PDF-"),
+        extract: pdf_extract,
+        describe: pdf_describe,
+    },
+];
+
+/// Detect the file format from its magic bytes and hash its canonical
+/// payload. A leading ID3v2 tag is skipped before probing, since taggers
+/// prepend it to MP3 and sometimes FLAC files. Returns the format name and
+/// hash, or None if the format is not supported or the file does not parse.
+fn payload_hash(path: &Path) -> io::Result<Option<(&'static str, String)>> {
+    let mut file = File::open(path)?;
+    let start = id3v2_tag_len(&mut file)?;
+
+    let mut probe = [0u8; PAYLOAD_PROBE_LEN];
+    file.seek(SeekFrom::Start(start))?;
+    let mut n = 0;
+    while n < probe.len() {
+        match file.read(&mut probe[n..])? {
+            0 => break,
+            read => n += read,
+        }
+    }
+
+    for extractor in PAYLOAD_EXTRACTORS {
+        if (extractor.matches)(&probe[..n]) {
+            let mut hasher = Context::new();
+            if (extractor.extract)(&mut file, start, &mut hasher)? {
+                return Ok(Some((extractor.name, format!("{:x}", hasher.compute()))));
+            }
+            return Ok(None);
+        }
+    }
+
+    Ok(None)
+}
+
+/// Total length of the ID3v2 tag(s) at the start of the file, or 0.
+fn id3v2_tag_len(file: &mut File) -> io::Result<u64> {
+    let mut pos = 0u64;
+    loop {
+        let mut header = [0u8; 10];
+        file.seek(SeekFrom::Start(pos))?;
+        if file.read(&mut header)? < 10 || &header[..3] != b"ID3" {
+            return Ok(pos);
+        }
+        // Size is "syncsafe": 7 bits per byte, excluding the 10-byte header
+        let size = header[6..10].iter().fold(0u64, |acc, b| (acc << 7) | (*b as u64 & 0x7F));
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        pos += 10 + size + footer;
+    }
+}
+
+/// Feed a PNG's image data into `hasher`: every chunk except the textual and
+/// timestamp/EXIF ancillary ones (tEXt, zTXt, iTXt, tIME, eXIf). IDAT data is
+/// hashed as one continuous stream, so the same image split into differently
+/// sized IDAT chunks still matches. Chunk CRCs are not hashed.
+fn png_extract(file: &mut File, start: u64, hasher: &mut Context) -> io::Result<bool> {
+    let mut reader = io::BufReader::with_capacity(1024 * 1024, file);
+    reader.seek(SeekFrom::Start(start + 8))?;
+
+    loop {
+        let mut header = [0u8; 8];
+        if reader.read_exact(&mut header).is_err() {
+            return Ok(false); // no IEND
+        }
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let chunk_type = [header[4], header[5], header[6], header[7]];
+
+        let skip = matches!(&chunk_type, b"tEXt" | b"zTXt" | b"iTXt" | b"tIME" | b"eXIf");
+        if !skip && &chunk_type != b"IDAT" {
+            hasher.consume(chunk_type);
+        }
+        let copied = if skip {
+            io::copy(&mut (&mut reader).take(len), &mut io::sink())?
+        } else {
+            io::copy(&mut (&mut reader).take(len), hasher)?
+        };
+        if copied != len {
+            return Ok(false);
+        }
+        reader.seek_relative(4)?; // CRC
+
+        if &chunk_type == b"IEND" {
+            return Ok(true);
+        }
+    }
+}
+
+/// Feed a FLAC file's audio into `hasher`: the STREAMINFO and SEEKTABLE
+/// blocks plus every audio frame. VORBIS_COMMENT (tags), PICTURE (cover art),
+/// PADDING, APPLICATION and CUESHEET blocks are skipped.
+fn flac_extract(file: &mut File, start: u64, hasher: &mut Context) -> io::Result<bool> {
+    let mut reader = io::BufReader::with_capacity(1024 * 1024, file);
+    reader.seek(SeekFrom::Start(start + 4))?;
+
+    loop {
+        let mut header = [0u8; 4];
+        if reader.read_exact(&mut header).is_err() {
+            return Ok(false);
+        }
+        let is_last = header[0] & 0x80 != 0;
+        let block_type = header[0] & 0x7F;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64;
+
+        let copied = if matches!(block_type, 0 | 3) {
+            hasher.consume([block_type]);
+            io::copy(&mut (&mut reader).take(len), hasher)?
+        } else {
+            io::copy(&mut (&mut reader).take(len), &mut io::sink())?
+        };
+        if copied != len {
+            return Ok(false);
+        }
+        if is_last {
+            break;
+        }
+    }
+
+    // Audio frames run to the end of the file
+    io::copy(&mut reader, hasher)?;
+    Ok(true)
+}
+
+/// Feed an MP3's audio frames into `hasher`: everything between the leading
+/// ID3v2 tag (already skipped by the caller) and the trailing tags, which are
+/// peeled off from the end in any order: ID3v1 (`TAG`, 128 bytes), APEv2
+/// (`APETAGEX` footer) and Lyrics3v2 (`LYRICS200`).
+fn mp3_extract(file: &mut File, start: u64, hasher: &mut Context) -> io::Result<bool> {
+    let mut end = file.metadata()?.len();
+
+    loop {
+        if end >= start + 128 {
+            let mut tag = [0u8; 3];
+            file.seek(SeekFrom::Start(end - 128))?;
+            file.read_exact(&mut tag)?;
+            if &tag == b"TAG" {
+                end -= 128;
+                continue;
+            }
+        }
+        if end >= start + 32 {
+            let mut footer = [0u8; 32];
+            file.seek(SeekFrom::Start(end - 32))?;
+            file.read_exact(&mut footer)?;
+            if &footer[..8] == b"APETAGEX" {
+                // Size covers items + footer; a header adds another 32 bytes
+                let size = u32::from_le_bytes([footer[12], footer[13], footer[14], footer[15]]) as u64;
+                let flags = u32::from_le_bytes([footer[20], footer[21], footer[22], footer[23]]);
+                let total = size + if flags & 0x8000_0000 != 0 { 32 } else { 0 };
+                if total > end - start {
+                    return Ok(false);
+                }
+                end -= total;
+                continue;
+            }
+        }
+        if end >= start + 15 {
+            let mut trailer = [0u8; 15];
+            file.seek(SeekFrom::Start(end - 15))?;
+            file.read_exact(&mut trailer)?;
+            if &trailer[6..] == b"LYRICS200" {
+                // 6 ASCII digits: size of the tag before this trailer
+                let size: u64 = match std::str::from_utf8(&trailer[..6]).ok().and_then(|d| d.parse().ok()) {
+                    Some(s) => s,
+                    None => return Ok(false),
+                };
+                if size + 15 > end - start {
+                    return Ok(false);
+                }
+                end -= size + 15;
+                continue;
+            }
+        }
+        break;
+    }
+
+    file.seek(SeekFrom::Start(start))?;
+    let copied = io::copy(&mut (&mut *file).take(end - start), hasher)?;
+    Ok(copied == end - start)
+}
+
+/// Feed a PDF's objects into `hasher`, leaving out what changes when only
+/// metadata is edited: the document Info dictionary (title, author, dates,
+/// producer), XMP `/Type /Metadata` streams, cross-reference tables/streams,
+/// the trailer (including its `/ID`) and all byte offsets. Objects are hashed
+/// by number, with later (incrementally updated) definitions replacing
+/// earlier ones. The whole file is read into memory, so PDFs larger than
+/// PDF_MAX_SIZE are not handled. Info dictionaries inside compressed object
+/// streams are not recognised.
+fn pdf_extract(file: &mut File, start: u64, hasher: &mut Context) -> io::Result<bool> {
+    const PDF_MAX_SIZE: u64 = 256 * 1024 * 1024;
+
+    if file.metadata()?.len() > PDF_MAX_SIZE {
+        return Ok(false);
+    }
+    let mut data = Vec::new();
+    file.seek(SeekFrom::Start(start))?;
+    file.read_to_end(&mut data)?;
+
+    let find = |haystack: &[u8], needle: &[u8], from: usize| -> Option<usize> {
+        if from >= haystack.len() {
+            return None;
+        }
+        haystack[from..].windows(needle.len()).position(|w| w == needle).map(|p| p + from)
+    };
+    let is_space = |b: u8| matches!(b, b' ' | b'\n' | b'\r' | b'\t' | b'\x0c' | 0);
+    // Parse "<digits>" ending right before `end` (after skipping whitespace)
+    let number_before = |end: usize| -> Option<(u64, usize)> {
+        let mut i = end;
+        while i > 0 && is_space(data[i - 1]) {
+            i -= 1;
+        }
+        let digits_end = i;
+        while i > 0 && data[i - 1].is_ascii_digit() {
+            i -= 1;
+        }
+        if i == digits_end {
+            return None;
+        }
+        std::str::from_utf8(&data[i..digits_end]).ok()?.parse().ok().map(|n| (n, i))
+    };
+
+    // Info dictionary object numbers: "/Info N G R" in any trailer
+    let mut info_objects: HashSet<u64> = HashSet::new();
+    let mut pos = 0;
+    while let Some(at) = find(&data, b"/Info", pos) {
+        let rest = &data[at + 5..data.len().min(at + 40)];
+        let text = String::from_utf8_lossy(rest);
+        let mut parts = text.split_whitespace();
+        if let (Some(num), Some(_gen), Some(r)) = (parts.next(), parts.next(), parts.next()) {
+            if r.starts_with('R') {
+                if let Ok(n) = num.parse() {
+                    info_objects.insert(n);
+                }
+            }
+        }
+        pos = at + 5;
+    }
+
+    // "N G obj ... endobj", jumping over stream data by /Length when direct
+    let mut objects: HashMap<u64, &[u8]> = HashMap::new();
+    let mut pos = 0;
+    while let Some(at) = find(&data, b"obj", pos) {
+        pos = at + 3;
+        let after_ok = data.get(at + 3).is_none_or(|b| is_space(*b) || *b == b'<' || *b == b'[');
+        if !after_ok || at == 0 || !is_space(data[at - 1]) {
+            continue;
+        }
+        let (_generation, gen_start) = match number_before(at) {
+            Some(v) => v,
+            None => continue,
+        };
+        let (number, _) = match number_before(gen_start) {
+            Some(v) => v,
+            None => continue,
+        };
+
+        let body_start = at + 3;
+        let mut search_from = body_start;
+        // Only this object can open a stream here: look no further than its
+        // first "endobj", and skip the tail of an "endstream"
+        let first_endobj = find(&data, b"endobj", body_start).unwrap_or(data.len());
+        let stream_at = find(&data[..first_endobj], b"stream", body_start)
+            .filter(|&s| s < 3 || &data[s - 3..s] != b"end");
+        if let Some(stream_at) = stream_at {
+            let dict = String::from_utf8_lossy(&data[body_start..stream_at]);
+            let direct_len = dict
+                .split("/Length")
+                .nth(1)
+                .and_then(|rest| {
+                    let mut parts = rest.split_whitespace();
+                    let len: usize = parts.next()?.trim_end_matches(['/', '>']).parse().ok()?;
+                    // "N G R" is an indirect length: can't use it
+                    match (parts.next(), parts.next()) {
+                        (Some(_), Some(r)) if r.starts_with('R') => None,
+                        _ => Some(len),
+                    }
+                });
+            search_from = match direct_len {
+                Some(len) => stream_at.saturating_add(6).saturating_add(len).min(data.len()),
+                None => find(&data, b"endstream", stream_at).unwrap_or(data.len()),
+            };
+        }
+        let end = match find(&data, b"endobj", search_from) {
+            Some(e) => e,
+            None => return Ok(false),
+        };
+        objects.insert(number, &data[body_start..end]);
+        pos = end + 6;
+    }
+
+    if objects.is_empty() {
+        return Ok(false);
+    }
+
+    let mut numbers: Vec<&u64> = objects.keys().collect();
+    numbers.sort();
+    for number in numbers {
+        let body = objects[number];
+        let head = String::from_utf8_lossy(&body[..body.len().min(512)]).replace(' ', "");
+        if info_objects.contains(number) || head.contains("/Type/Metadata") || head.contains("/Type/XRef") {
+            continue;
+        }
+        hasher.consume(number.to_string().as_bytes());
+        hasher.consume(b" obj");
+        hasher.consume(body);
+    }
+
+    Ok(true)
+}
+
+/// Feed an MP4/MOV file's media into `hasher`: every `mdat` payload, plus the per-track
+/// sample tables (stsd, stts, stss, ctts, stsc, stsz, stz2, sdtp) that say
+/// how that payload splits into frames. Metadata atoms are ignored — `udta`,
+/// `meta`, `free`/`skip`, `uuid`, the mvhd/tkhd/mdhd timestamps — and so are
+/// `stco`/`co64`, whose absolute offsets change whenever metadata before
+/// `mdat` grows or `moov` moves. The two parts are hashed separately, so the
+/// position of `moov` (start or end of the file) doesn't matter either.
+/// Returns false if the atom structure is malformed or there is no `mdat`.
+fn mp4_extract(file: &mut File, start: u64, hasher: &mut Context) -> io::Result<bool> {
+    let file_len = file.metadata()?.len();
+    let mut media = Context::new();
+    let mut tables = Context::new();
+    let mut found_mdat = false;
+
+    if !mp4_walk_atoms(file, start, file_len, 0, &mut media, &mut tables, &mut found_mdat)? || !found_mdat {
+        return Ok(false);
+    }
+
+    hasher.consume(media.compute().0);
+    hasher.consume(tables.compute().0);
+    Ok(true)
+}
+
+/// Walk the atoms in `[start, end)`, descending into the containers that
+/// lead to the sample tables. Returns false on a malformed atom header.
+fn mp4_walk_atoms(
+    file: &mut File,
+    start: u64,
+    end: u64,
+    depth: usize,
+    media: &mut Context,
+    tables: &mut Context,
+    found_mdat: &mut bool,
+) -> io::Result<bool> {
+    const MAX_DEPTH: usize = 8;
+
+    let mut pos = start;
+    while pos + 8 <= end {
+        file.seek(SeekFrom::Start(pos))?;
+        let mut header = [0u8; 8];
+        file.read_exact(&mut header)?;
+        let size32 = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let atom_type = [header[4], header[5], header[6], header[7]];
+
+        let (size, header_len) = match size32 {
+            0 => (end - pos, 8), // extends to the end of the enclosing space
+            1 => {
+                let mut large = [0u8; 8];
+                file.read_exact(&mut large)?;
+                (u64::from_be_bytes(large), 16)
+            }
+            n => (n, 8),
+        };
+        // A 64-bit largesize from the file may be anything: no wrapping
+        let atom_end = match pos.checked_add(size) {
+            Some(atom_end) if size >= header_len && atom_end <= end => atom_end,
+            _ => return Ok(false),
+        };
+        let body_start = pos + header_len;
+        let body_len = size - header_len;
+
+        match &atom_type {
+            b"mdat" => {
+                file.seek(SeekFrom::Start(body_start))?;
+                let copied = io::copy(&mut (&mut *file).take(body_len), media)?;
+                if copied != body_len {
+                    return Ok(false);
+                }
+                *found_mdat = true;
+            }
+            b"moov" | b"trak" | b"mdia" | b"minf" | b"stbl"
+                if depth < MAX_DEPTH && !mp4_walk_atoms(file, body_start, atom_end, depth + 1, media, tables, found_mdat)? =>
+            {
+                return Ok(false);
+            }
+            b"stsd" | b"stts" | b"stss" | b"ctts" | b"stsc" | b"stsz" | b"stz2" | b"sdtp" => {
+                tables.consume(atom_type);
+                file.seek(SeekFrom::Start(body_start))?;
+                io::copy(&mut (&mut *file).take(body_len), tables)?;
+            }
+            _ => {} // metadata or otherwise irrelevant: skip
+        }
+
+        pos = atom_end;
+    }
+
+    Ok(true)
+}
+
+/// Feed a JPEG's image data into `hasher`, skipping APP0–APP15 (EXIF, XMP, ICC, ...) and
+/// COM segments as well as anything after EOI (maker trailers). Quantization
+/// and Huffman tables, frame/scan headers and the entropy-coded scan data
+/// are hashed, so files that differ only in metadata — even in metadata
+/// length — hash the same. Reads through a buffered stream, never the
+/// whole file. Returns false if the stream is not a well-formed JPEG.
+fn jpeg_extract(file: &mut File, start: u64, hasher: &mut Context) -> io::Result<bool> {
+    use std::io::BufRead;
+
+    file.seek(SeekFrom::Start(start))?;
+    let mut reader = io::BufReader::with_capacity(1024 * 1024, file);
+    let mut byte = [0u8; 1];
+    let mut read_byte = |reader: &mut io::BufReader<&mut File>| -> io::Result<Option<u8>> {
+        match reader.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    };
+
+    // SOI
+    if read_byte(&mut reader)? != Some(0xFF) || read_byte(&mut reader)? != Some(0xD8) {
+        return Ok(false);
+    }
+
+    // Set when the end of a scan was detected together with its marker byte
+    let mut pending_marker: Option<u8> = None;
+
+    loop {
+        // Find the next marker (skipping fill bytes)
+        let mut marker = match pending_marker.take() {
+            Some(m) => m,
+            None => {
+                if read_byte(&mut reader)? != Some(0xFF) {
+                    return Ok(false);
+                }
+                match read_byte(&mut reader)? {
+                    Some(m) => m,
+                    None => return Ok(false),
+                }
+            }
+        };
+        while marker == 0xFF {
+            marker = match read_byte(&mut reader)? {
+                Some(m) => m,
+                None => return Ok(false),
+            };
+        }
+
+        match marker {
+            0xD9 => break, // EOI: ignore anything that follows
+            0x01 | 0xD0..=0xD7 => {
+                // Standalone markers without a length field
+                hasher.consume([0xFF, marker]);
+                continue;
+            }
+            _ => {}
+        }
+
+        let mut len_bytes = [0u8; 2];
+        reader.read_exact(&mut len_bytes)?;
+        let len = u16::from_be_bytes(len_bytes) as u64;
+        if len < 2 {
+            return Ok(false);
+        }
+        let body_len = len - 2;
+
+        let is_metadata = matches!(marker, 0xE0..=0xEF | 0xFE);
+        if is_metadata {
+            let skipped = io::copy(&mut (&mut reader).take(body_len), &mut io::sink())?;
+            if skipped != body_len {
+                return Ok(false);
+            }
+            continue;
+        }
+
+        hasher.consume([0xFF, marker]);
+        hasher.consume(len_bytes);
+        let mut body = vec![0u8; body_len as usize];
+        reader.read_exact(&mut body)?;
+        hasher.consume(&body);
+
+        if marker == 0xDA {
+            // SOS: entropy-coded data runs until a marker other than a
+            // stuffed 0xFF00 or a restart marker. Hash it in buffer-sized
+            // pieces, stopping right before the terminating 0xFF.
+            loop {
+                let buf = reader.fill_buf()?;
+                if buf.is_empty() {
+                    return Ok(false); // truncated scan
+                }
+                let mut end = None;
+                let mut i = 0;
+                while i < buf.len() {
+                    if buf[i] == 0xFF {
+                        if i + 1 >= buf.len() {
+                            break; // need the next byte to decide
+                        }
+                        let next = buf[i + 1];
+                        if next == 0x00 || (0xD0..=0xD7).contains(&next) {
+                            i += 2;
+                            continue;
+                        }
+                        end = Some(i);
+                        break;
+                    }
+                    i += 1;
+                }
+                match end {
+                    Some(e) => {
+                        hasher.consume(&buf[..e]);
+                        reader.consume(e);
+                        break;
+                    }
+                    None if i == 0 => {
+                        // Lone 0xFF at the end of the buffer: pull it through
+                        // together with its successor.
+                        let mut pair = [0u8; 2];
+                        reader.read_exact(&mut pair)?;
+                        if pair[1] == 0x00 || (0xD0..=0xD7).contains(&pair[1]) {
+                            hasher.consume(pair);
+                        } else {
+                            // A real marker: hand it to the marker loop
+                            pending_marker = Some(pair[1]);
+                            break;
+                        }
+                    }
+                    None => {
+                        hasher.consume(&buf[..i]);
+                        reader.consume(i);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+/// Differing bytes at most this far apart are reported as one region.
+const DIFF_REGION_GAP: u64 = 16;
+/// Regions listed per pair; the rest are only counted.
+const DIFF_REGION_LIMIT: usize = 32;
+/// Bytes of hex/ASCII dump shown per region; longer regions are cut.
+const DIFF_DUMP_LIMIT: u64 = 256;
+
+/// A run of differing bytes `[offset, offset + len)`, coalesced over gaps of
+/// up to DIFF_REGION_GAP equal bytes. A size difference shows up as a
+/// region covering the longer file's tail.
+struct DiffRegion {
+    offset: u64,
+    len: u64,
+}
+
+/// --show-diff / --hexdiff: print where two files differ, region by
+/// region, with a hex/ASCII side-by-side dump and — for formats known to
+/// PAYLOAD_EXTRACTORS — the structure each region falls into.
+fn show_file_diff(path_a: &Path, path_b: &Path) -> io::Result<()> {
+    let (regions, bytes_differing) = byte_diff_regions(path_a, path_b)?;
+    let len_a = std::fs::metadata(path_a)?.len();
+    let len_b = std::fs::metadata(path_b)?.len();
+
+    println!("Diff: {} <-> {}", path_a.display(), path_b.display());
+    if len_a != len_b {
+        println!("  Sizes differ: {} vs {} bytes", len_a, len_b);
+    }
+    if regions.is_empty() {
+        println!("  Files are identical");
+        return Ok(());
+    }
+    println!("  {} differing bytes in {} region(s)", bytes_differing, regions.len());
+
+    let labels = structure_labels(path_a).unwrap_or_default();
+    let mut fa = File::open(path_a)?;
+    let mut fb = File::open(path_b)?;
+
+    for (i, region) in regions.iter().take(DIFF_REGION_LIMIT).enumerate() {
+        let names = region_label(&labels, region);
+        println!(
+            "  Region {}: offset 0x{:08x} ({}), {} bytes{}",
+            i + 1, region.offset, region.offset, region.len,
+            if names.is_empty() { String::new() } else { format!(" — {}", names) }
+        );
+
+        // Dump whole 16-byte rows around the region, cut at DIFF_DUMP_LIMIT
+        let dump_start = region.offset & !15;
+        let dump_end = (region.offset + region.len.min(DIFF_DUMP_LIMIT) + 15) & !15;
+        let bytes_a = read_range(&mut fa, dump_start, dump_end - dump_start)?;
+        let bytes_b = read_range(&mut fb, dump_start, dump_end - dump_start)?;
+        for row in (0..dump_end - dump_start).step_by(16) {
+            let in_region = |pos: u64| pos >= region.offset && pos < region.offset + region.len;
+            let side = |bytes: &[u8]| -> (String, String) {
+                let mut hex = String::new();
+                let mut ascii = String::new();
+                for k in row..row + 16 {
+                    match bytes.get(k as usize) {
+                        Some(&b) => {
+                            hex.push_str(&format!("{:02x}", b));
+                            ascii.push(if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' });
+                        }
+                        None => {
+                            hex.push_str("  ");
+                            ascii.push(' ');
+                        }
+                    }
+                    // A '*' after a byte marks it as differing
+                    hex.push(if in_region(dump_start + k) && bytes_a.get(k as usize) != bytes_b.get(k as usize) { '*' } else { ' ' });
+                }
+                (hex, ascii)
+            };
+            let (hex_a, ascii_a) = side(&bytes_a);
+            let (hex_b, ascii_b) = side(&bytes_b);
+            println!("    {:08x}  {} |{}|   {} |{}|", dump_start + row, hex_a, ascii_a, hex_b, ascii_b);
+        }
+        if region.len > DIFF_DUMP_LIMIT {
+            println!("    ... {} more bytes in this region", region.len - DIFF_DUMP_LIMIT);
+        }
+    }
+    if regions.len() > DIFF_REGION_LIMIT {
+        println!("  ... {} more region(s)", regions.len() - DIFF_REGION_LIMIT);
+    }
+    println!();
+
+    Ok(())
+}
+
+/// Compare two files position by position (1 MB at a time) and return the
+/// coalesced differing regions and the total number of differing bytes
+/// (bytes past the end of the shorter file count as differing).
+fn byte_diff_regions(path_a: &Path, path_b: &Path) -> io::Result<(Vec<DiffRegion>, u64)> {
+    const CHUNK: usize = 1024 * 1024;
+
+    let mut fa = File::open(path_a)?;
+    let mut fb = File::open(path_b)?;
+    let mut buf_a = vec![0u8; CHUNK];
+    let mut buf_b = vec![0u8; CHUNK];
+
+    let mut regions: Vec<DiffRegion> = Vec::new();
+    let mut bytes_differing = 0u64;
+    let mut pos = 0u64;
+    let note = |regions: &mut Vec<DiffRegion>, at: u64, len: u64| {
+        match regions.last_mut() {
+            Some(last) if at <= last.offset + last.len + DIFF_REGION_GAP => {
+                last.len = at + len - last.offset;
+            }
+            _ => regions.push(DiffRegion { offset: at, len }),
+        }
+    };
+
+    loop {
+        let na = read_full(&mut fa, &mut buf_a)?;
+        let nb = read_full(&mut fb, &mut buf_b)?;
+        if na == 0 && nb == 0 {
+            break;
+        }
+        let common = na.min(nb);
+        if buf_a[..common] != buf_b[..common] {
+            for k in 0..common {
+                if buf_a[k] != buf_b[k] {
+                    note(&mut regions, pos + k as u64, 1);
+                    bytes_differing += 1;
+                }
+            }
+        }
+        if na != nb {
+            // One file ended: the rest of the other one is a single region
+            let tail_start = pos + common as u64;
+            let remaining = if na > nb { &mut fa } else { &mut fb };
+            let tail_len = (na.max(nb) - common) as u64 + io::copy(remaining, &mut io::sink())?;
+            note(&mut regions, tail_start, tail_len);
+            bytes_differing += tail_len;
+            break;
+        }
+        pos += common as u64;
+    }
+
+    Ok((regions, bytes_differing))
+}
+
+/// Counters for the direct comparison of two-file groups.
+#[derive(Debug, Default)]
+struct PairCompareStats {
+    pairs: u64,
+    differed: u64,
+    bytes_skipped: u64, // bytes hashing both files in full would have read on top
+}
+
+/// For a group of exactly two files on disk, compare them block by block and
+/// stop at the first difference instead of hashing both in full. Returns
+/// None for any other group, which is then hashed as usual.
+fn compare_pair_directly(group: &[FileInfo], pair_stats: &Mutex<PairCompareStats>) -> io::Result<Option<bool>> {
+    let [a, b] = group else { return Ok(None) };
+    if a.archive_member.is_some() || b.archive_member.is_some() {
+        return Ok(None);
+    }
+
+    let mut file_a = File::open(&a.path)?;
+    let mut file_b = File::open(&b.path)?;
+    let mut buf_a = vec![0u8; 1024 * 1024];
+    let mut buf_b = vec![0u8; 1024 * 1024];
+    let mut bytes_read = 0u64;
+    let equal = loop {
+        let n_a = read_full(&mut file_a, &mut buf_a)?;
+        let n_b = read_full(&mut file_b, &mut buf_b)?;
+        bytes_read += (n_a + n_b) as u64;
+        if n_a != n_b || buf_a[..n_a] != buf_b[..n_b] {
+            break false;
+        }
+        if n_a == 0 {
+            break true;
+        }
+    };
+
+    let mut stats = pair_stats.lock().unwrap();
+    stats.pairs += 1;
+    if !equal {
+        stats.differed += 1;
+    }
+    stats.bytes_skipped += (a.size + b.size).saturating_sub(bytes_read);
+    Ok(Some(equal))
+}
+
+/// Read until `buf` is full or the file ends.
+fn read_full(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match file.read(&mut buf[n..])? {
+            0 => break,
+            read => n += read,
+        }
+    }
+    Ok(n)
+}
+
+/// Up to `len` bytes starting at `start` (fewer at the end of the file).
+fn read_range(file: &mut File, start: u64, len: u64) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![0u8; len as usize];
+    file.seek(SeekFrom::Start(start))?;
+    let n = read_full(file, &mut bytes)?;
+    bytes.truncate(n);
+    Ok(bytes)
+}
+
+/// The innermost labelled structures overlapping `region`, comma-separated
+/// (at most three, then "..."). Empty if nothing is known about the range.
+fn region_label(labels: &[StructureLabel], region: &DiffRegion) -> String {
+    let region_end = region.offset + region.len;
+    let overlapping: Vec<&StructureLabel> = labels
+        .iter()
+        .filter(|l| l.start < region_end && l.end > region.offset)
+        .collect();
+    // Keep only leaves: labels with no other overlapping label nested inside
+    let mut leaves: Vec<&StructureLabel> = overlapping
+        .iter()
+        .filter(|l| {
+            !overlapping
+                .iter()
+                .any(|o| o.start >= l.start && o.end <= l.end && o.end - o.start < l.end - l.start)
+        })
+        .copied()
+        .collect();
+    leaves.sort_by_key(|l| l.start);
+    leaves.dedup_by_key(|l| (l.start, l.end));
+
+    let mut names: Vec<&str> = leaves.iter().take(3).map(|l| l.name.as_str()).collect();
+    if leaves.len() > 3 {
+        names.push("...");
+    }
+    names.join(", ")
+}
+
+/// Label the structures of a file in a format known to PAYLOAD_EXTRACTORS
+/// (plus any leading ID3v2 tag), for the visual diff.
+fn structure_labels(path: &Path) -> io::Result<Vec<StructureLabel>> {
+    let mut file = File::open(path)?;
+    let mut labels = Vec::new();
+    let start = id3v2_tag_len(&mut file)?;
+    if start > 0 {
+        id3v2_describe(&mut file, start, &mut labels)?;
+    }
+
+    let probe = read_range(&mut file, start, PAYLOAD_PROBE_LEN as u64)?;
+    if let Some(extractor) = PAYLOAD_EXTRACTORS.iter().find(|e| (e.matches)(&probe)) {
+        (extractor.describe)(&mut file, start, &mut labels)?;
+    }
+    Ok(labels)
+}
+
+/// Label the ID3v2 tag(s) in `[0, end)` and their frames (v2.3/v2.4 layout).
+fn id3v2_describe(file: &mut File, end: u64, labels: &mut Vec<StructureLabel>) -> io::Result<()> {
+    let mut pos = 0u64;
+    while pos + 10 <= end {
+        let header = read_range(file, pos, 10)?;
+        if header.len() < 10 || &header[..3] != b"ID3" {
+            break;
+        }
+        let size = header[6..10].iter().fold(0u64, |acc, b| (acc << 7) | (*b as u64 & 0x7F));
+        let tag_end = (pos + 10 + size).min(end);
+        labels.push(StructureLabel { start: pos, end: tag_end, name: format!("ID3v2.{} tag", header[3]) });
+
+        let mut frame = pos + 10;
+        while frame + 10 <= tag_end {
+            let frame_header = read_range(file, frame, 10)?;
+            if frame_header.len() < 10 || frame_header[0] == 0 {
+                break; // padding
+            }
+            let id = String::from_utf8_lossy(&frame_header[..4]).into_owned();
+            let len = if header[3] >= 4 {
+                frame_header[4..8].iter().fold(0u64, |acc, b| (acc << 7) | (*b as u64 & 0x7F))
+            } else {
+                u32::from_be_bytes([frame_header[4], frame_header[5], frame_header[6], frame_header[7]]) as u64
+            };
+            let frame_end = (frame + 10 + len).min(tag_end);
+            labels.push(StructureLabel { start: frame, end: frame_end, name: format!("ID3v2 frame {}", id) });
+            frame = frame_end;
+        }
+        pos = tag_end;
+    }
+    Ok(())
+}
+
+/// Label JPEG segments, and inside an EXIF APP1 segment every IFD entry by
+/// tag name (entry plus out-of-line value), e.g. "EXIF DateTimeOriginal".
+/// Everything from the first SOS on is labelled as scan data.
+fn jpeg_describe(file: &mut File, start: u64, labels: &mut Vec<StructureLabel>) -> io::Result<()> {
+    let file_len = file.metadata()?.len();
+    let mut pos = start + 2;
+    labels.push(StructureLabel { start, end: pos, name: "JPEG SOI".to_string() });
+
+    while pos + 4 <= file_len {
+        let header = read_range(file, pos, 4)?;
+        if header.len() < 4 || header[0] != 0xFF {
+            break;
+        }
+        let marker = header[1];
+        let len = u16::from_be_bytes([header[2], header[3]]) as u64;
+        let segment_end = (pos + 2 + len).min(file_len);
+        let name = match marker {
+            0xE0 => "JPEG APP0 (JFIF)".to_string(),
+            0xE1 => "JPEG APP1 (EXIF/XMP)".to_string(),
+            0xE2 => "JPEG APP2 (ICC profile)".to_string(),
+            0xE3..=0xEF => format!("JPEG APP{}", marker - 0xE0),
+            0xFE => "JPEG comment".to_string(),
+            0xDB => "JPEG quantization table".to_string(),
+            0xC4 => "JPEG Huffman table".to_string(),
+            0xC0..=0xCF => "JPEG frame header".to_string(),
+            0xDA => "JPEG scan header".to_string(),
+            _ => format!("JPEG segment 0x{:02X}", marker),
+        };
+        labels.push(StructureLabel { start: pos, end: segment_end, name });
+
+        if marker == 0xE1 {
+            let body = read_range(file, pos + 4, len.saturating_sub(2))?;
+            if body.starts_with(b"Exif\0\0") {
+                exif_describe(&body[6..], pos + 10, labels);
+            }
+        }
+        if marker == 0xDA {
+            labels.push(StructureLabel { start: segment_end, end: file_len, name: "JPEG scan data".to_string() });
+            break;
+        }
+        pos = segment_end;
+    }
+    Ok(())
+}
+
+/// Label the entries of a TIFF structure (EXIF payload) starting at file
+/// offset `base`: IFD0, its EXIF and GPS sub-IFDs and IFD1 (thumbnail).
+fn exif_describe(tiff: &[u8], base: u64, labels: &mut Vec<StructureLabel>) {
+    let little_endian = match tiff.get(..2) {
+        Some(b"II") => true,
+        Some(b"MM") => false,
+        _ => return,
+    };
+    let u16_at = |at: usize| -> Option<u16> {
+        let b = tiff.get(at..at + 2)?;
+        Some(if little_endian { u16::from_le_bytes([b[0], b[1]]) } else { u16::from_be_bytes([b[0], b[1]]) })
+    };
+    let u32_at = |at: usize| -> Option<u32> {
+        let b = tiff.get(at..at + 4)?;
+        let b = [b[0], b[1], b[2], b[3]];
+        Some(if little_endian { u32::from_le_bytes(b) } else { u32::from_be_bytes(b) })
+    };
+
+    // (IFD offset, is GPS IFD); bounded so a looping chain can't run forever
+    let mut pending = match u32_at(4) {
+        Some(ifd0) => vec![(ifd0 as usize, false, "IFD0")],
+        None => return,
+    };
+    let mut visited = HashSet::new();
+    while let Some((ifd, gps, ifd_name)) = pending.pop() {
+        if ifd == 0 || !visited.insert(ifd) || visited.len() > 8 {
+            continue;
+        }
+        let count = match u16_at(ifd) {
+            Some(c) => c as usize,
+            None => continue,
+        };
+        let mut thumbnail: (Option<u32>, Option<u32>) = (None, None);
+        for i in 0..count {
+            let entry = ifd + 2 + i * 12;
+            let (tag, kind, n) = match (u16_at(entry), u16_at(entry + 2), u32_at(entry + 4)) {
+                (Some(t), Some(k), Some(n)) => (t, k, n as u64),
+                _ => break,
+            };
+            let name = if gps { gps_tag_name(tag) } else { exif_tag_name(tag) };
+            let name = match name {
+                Some(n) => format!("EXIF {}", n),
+                None => format!("EXIF {} tag 0x{:04X}", ifd_name, tag),
+            };
+            labels.push(StructureLabel { start: base + entry as u64, end: base + entry as u64 + 12, name: name.clone() });
+
+            let unit = match kind {
+                1 | 2 | 6 | 7 => 1,
+                3 | 8 => 2,
+                4 | 9 | 11 => 4,
+                5 | 10 | 12 => 8,
+                _ => 0,
+            };
+            let value_len = unit * n;
+            let value = u32_at(entry + 8).unwrap_or(0);
+            if value_len > 4 {
+                labels.push(StructureLabel { start: base + value as u64, end: base + value as u64 + value_len, name });
+            }
+            match (gps, tag) {
+                (false, 0x8769) => pending.push((value as usize, false, "EXIF")),
+                (false, 0x8825) => pending.push((value as usize, true, "GPS")),
+                (false, 0xA005) => pending.push((value as usize, false, "Interop")),
+                (false, 0x0201) => thumbnail.0 = Some(value),
+                (false, 0x0202) => thumbnail.1 = Some(value),
+                _ => {}
+            }
+        }
+        if let (Some(offset), Some(len)) = thumbnail {
+            labels.push(StructureLabel {
+                start: base + offset as u64,
+                end: base + offset as u64 + len as u64,
+                name: "EXIF thumbnail image".to_string(),
+            });
+        }
+        if ifd_name == "IFD0" {
+            if let Some(next) = u32_at(ifd + 2 + count * 12) {
+                pending.push((next as usize, false, "IFD1"));
+            }
+        }
+    }
+}
+
+/// Names of common EXIF/TIFF tags (IFD0, IFD1, EXIF and Interop IFDs).
+fn exif_tag_name(tag: u16) -> Option<&'static str> {
+    Some(match tag {
+        0x010E => "ImageDescription",
+        0x010F => "Make",
+        0x0110 => "Model",
+        0x0112 => "Orientation",
+        0x011A => "XResolution",
+        0x011B => "YResolution",
+        0x0128 => "ResolutionUnit",
+        0x0131 => "Software",
+        0x0132 => "DateTime",
+        0x013B => "Artist",
+        0x0201 => "ThumbnailOffset",
+        0x0202 => "ThumbnailLength",
+        0x0213 => "YCbCrPositioning",
+        0x8298 => "Copyright",
+        0x829A => "ExposureTime",
+        0x829D => "FNumber",
+        0x8769 => "ExifIFDPointer",
+        0x8822 => "ExposureProgram",
+        0x8825 => "GPSInfoIFDPointer",
+        0x8827 => "ISOSpeedRatings",
+        0x9000 => "ExifVersion",
+        0x9003 => "DateTimeOriginal",
+        0x9004 => "DateTimeDigitized",
+        0x9010 => "OffsetTime",
+        0x9011 => "OffsetTimeOriginal",
+        0x9012 => "OffsetTimeDigitized",
+        0x9201 => "ShutterSpeedValue",
+        0x9202 => "ApertureValue",
+        0x9204 => "ExposureBiasValue",
+        0x9207 => "MeteringMode",
+        0x9209 => "Flash",
+        0x920A => "FocalLength",
+        0x927C => "MakerNote",
+        0x9286 => "UserComment",
+        0x9290 => "SubSecTime",
+        0x9291 => "SubSecTimeOriginal",
+        0x9292 => "SubSecTimeDigitized",
+        0xA000 => "FlashpixVersion",
+        0xA001 => "ColorSpace",
+        0xA002 => "PixelXDimension",
+        0xA003 => "PixelYDimension",
+        0xA005 => "InteropIFDPointer",
+        0xA420 => "ImageUniqueID",
+        0xA434 => "LensModel",
+        _ => return None,
+    })
+}
+
+/// Names of common GPS IFD tags.
+fn gps_tag_name(tag: u16) -> Option<&'static str> {
+    Some(match tag {
+        0x00 => "GPSVersionID",
+        0x01 => "GPSLatitudeRef",
+        0x02 => "GPSLatitude",
+        0x03 => "GPSLongitudeRef",
+        0x04 => "GPSLongitude",
+        0x05 => "GPSAltitudeRef",
+        0x06 => "GPSAltitude",
+        0x07 => "GPSTimeStamp",
+        0x10 => "GPSImgDirectionRef",
+        0x11 => "GPSImgDirection",
+        0x1D => "GPSDateStamp",
+        _ => return None,
+    })
+}
+
+/// Label PNG chunks by type (length, type, data and CRC).
+fn png_describe(file: &mut File, start: u64, labels: &mut Vec<StructureLabel>) -> io::Result<()> {
+    let file_len = file.metadata()?.len();
+    labels.push(StructureLabel { start, end: start + 8, name: "PNG signature".to_string() });
+    let mut pos = start + 8;
+    while pos + 12 <= file_len {
+        let header = read_range(file, pos, 8)?;
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let chunk_end = (pos + 12 + len).min(file_len);
+        let chunk_type = String::from_utf8_lossy(&header[4..8]).into_owned();
+        labels.push(StructureLabel { start: pos, end: chunk_end, name: format!("PNG {} chunk", chunk_type) });
+        if chunk_type == "IEND" {
+            break;
+        }
+        pos = chunk_end;
+    }
+    Ok(())
+}
+
+/// Label MP4/MOV atoms by path, e.g. "MP4 moov/udta", descending into the
+/// usual containers.
+fn mp4_describe(file: &mut File, start: u64, labels: &mut Vec<StructureLabel>) -> io::Result<()> {
+    let file_len = file.metadata()?.len();
+    let mut stack = vec![(start, file_len, String::new(), 0usize)];
+    while let Some((mut pos, end, parent, depth)) = stack.pop() {
+        while pos + 8 <= end {
+            let header = read_range(file, pos, 16)?;
+            if header.len() < 8 {
                 break;
             }
-            hasher.consume(&buffer[..bytes_read]);
+            let size32 = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+            let (size, header_len) = match size32 {
+                0 => (end - pos, 8),
+                1 if header.len() == 16 => (u64::from_be_bytes([
+                    header[8], header[9], header[10], header[11], header[12], header[13], header[14], header[15],
+                ]), 16),
+                n => (n, 8),
+            };
+            let atom_end = match pos.checked_add(size) {
+                Some(atom_end) if size >= header_len && atom_end <= end => atom_end,
+                _ => break,
+            };
+            let atom_type = String::from_utf8_lossy(&header[4..8]).into_owned();
+            let path = if parent.is_empty() { atom_type.clone() } else { format!("{}/{}", parent, atom_type) };
+            labels.push(StructureLabel { start: pos, end: atom_end, name: format!("MP4 {}", path) });
+            if matches!(atom_type.as_str(), "moov" | "trak" | "mdia" | "minf" | "stbl" | "udta" | "edts") && depth < 8 {
+                stack.push((pos + header_len, atom_end, path, depth + 1));
+            }
+            pos = atom_end;
         }
-
-        let result = hasher.compute();
-        Ok(Some(format!("{:x}", result)))
     }
+    Ok(())
 }
 
-/// Compare two same-size files chunk by chunk, counting differing bytes.
-/// Never loads the full file into RAM — uses a 1MB rolling buffer matching
-/// the existing I/O strategy. Always reads both files completely so the
-/// caller gets the exact differing byte count even when it exceeds threshold.
-/// Returns None if either file cannot be opened/read.
-fn chunked_fuzzy_compare(path_a: &Path, path_b: &Path, debug: bool) -> io::Result<u64> {
-    const CHUNK: usize = 1024 * 1024; // 1 MB, matches existing buffer size
+/// Label FLAC metadata blocks by type, and the audio frames after them.
+fn flac_describe(file: &mut File, start: u64, labels: &mut Vec<StructureLabel>) -> io::Result<()> {
+    let file_len = file.metadata()?.len();
+    let mut pos = start + 4;
+    while pos + 4 <= file_len {
+        let header = read_range(file, pos, 4)?;
+        let is_last = header[0] & 0x80 != 0;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64;
+        let block_end = (pos + 4 + len).min(file_len);
+        let name = match header[0] & 0x7F {
+            0 => "STREAMINFO",
+            1 => "PADDING",
+            2 => "APPLICATION",
+            3 => "SEEKTABLE",
+            4 => "VORBIS_COMMENT (tags)",
+            5 => "CUESHEET",
+            6 => "PICTURE",
+            _ => "unknown",
+        };
+        labels.push(StructureLabel { start: pos, end: block_end, name: format!("FLAC {} block", name) });
+        pos = block_end;
+        if is_last {
+            break;
+        }
+    }
+    labels.push(StructureLabel { start: pos, end: file_len, name: "FLAC audio frames".to_string() });
+    Ok(())
+}
 
-    let mut fa = File::open(path_a)?;
-    let mut fb = File::open(path_b)?;
+/// Label an MP3's audio frames and a trailing ID3v1 tag.
+fn mp3_describe(file: &mut File, start: u64, labels: &mut Vec<StructureLabel>) -> io::Result<()> {
+    let file_len = file.metadata()?.len();
+    let mut audio_end = file_len;
+    if file_len >= start + 128 && read_range(file, file_len - 128, 3)? == b"TAG" {
+        audio_end = file_len - 128;
+        labels.push(StructureLabel { start: audio_end, end: file_len, name: "ID3v1 tag".to_string() });
+    }
+    labels.push(StructureLabel { start, end: audio_end, name: "MP3 audio frames".to_string() });
+    Ok(())
+}
 
-    let mut buf_a = vec![0u8; CHUNK];
-    let mut buf_b = vec![0u8; CHUNK];
-    let mut total_diff: u64 = 0;
+/// Label PDF objects ("N G obj ... endobj"), xref tables and trailers.
+/// Uses plain keyword search, so it can be fooled by keywords inside
+/// streams; good enough to point a reviewer at the right place.
+fn pdf_describe(file: &mut File, start: u64, labels: &mut Vec<StructureLabel>) -> io::Result<()> {
+    const PDF_MAX_SIZE: u64 = 256 * 1024 * 1024;
 
-    loop {
-        let na = fa.read(&mut buf_a)?;
-        let nb = fb.read(&mut buf_b)?;
+    if file.metadata()?.len() > PDF_MAX_SIZE {
+        return Ok(());
+    }
+    let mut data = Vec::new();
+    file.seek(SeekFrom::Start(start))?;
+    file.read_to_end(&mut data)?;
+    let find = |needle: &[u8], from: usize| -> Option<usize> {
+        data.get(from..)?.windows(needle.len()).position(|w| w == needle).map(|p| p + from)
+    };
 
-        // Both files are same size (precondition from size grouping), so na == nb
-        // at every chunk. If they somehow diverge, stop — something is wrong.
-        if na == 0 && nb == 0 {
-            break;
+    let mut pos = 0;
+    while let Some(at) = find(b" obj", pos) {
+        pos = at + 4;
+        // "N G obj": walk back over the generation and object numbers
+        let head = &data[..at];
+        let gen_start = head.iter().rposition(|b| !b.is_ascii_digit()).map_or(0, |p| p + 1);
+        if gen_start == at || gen_start == 0 {
+            continue;
         }
-        if na != nb {
-            if debug {
-                eprintln!(
-                    "[FUZZY] Chunk size mismatch ({} vs {}) for {} <-> {} — aborting",
-                    na, nb, path_a.display(), path_b.display()
-                );
+        let num_end = gen_start - 1;
+        let num_start = head[..num_end].iter().rposition(|b| !b.is_ascii_digit()).map_or(0, |p| p + 1);
+        if num_start == num_end {
+            continue;
+        }
+        let number = String::from_utf8_lossy(&data[num_start..num_end]).into_owned();
+        let end = find(b"endobj", pos).map_or(data.len(), |e| e + 6);
+        labels.push(StructureLabel {
+            start: start + num_start as u64,
+            end: start + end as u64,
+            name: format!("PDF object {}", number),
+        });
+        pos = end;
+    }
+    for (keyword, name) in [(&b"xref"[..], "PDF xref table"), (&b"trailer"[..], "PDF trailer")] {
+        let mut pos = 0;
+        while let Some(at) = find(keyword, pos) {
+            let end = find(b"startxref", at + keyword.len()).or_else(|| find(b"%%EOF", at)).unwrap_or(data.len());
+            if !(keyword == b"xref" && at > 0 && data[at - 1] == b't') {
+                labels.push(StructureLabel { start: start + at as u64, end: start + end as u64, name: name.to_string() });
             }
-            // Treat divergent read as maximum difference so the pair is rejected.
-            return Ok(u64::MAX);
+            pos = at + keyword.len();
         }
-
-        let chunk_diff = buf_a[..na]
-            .iter()
-            .zip(buf_b[..nb].iter())
-            .filter(|(a, b)| a != b)
-            .count() as u64;
-        total_diff += chunk_diff;
     }
-
-    Ok(total_diff)
+    Ok(())
 }
 
 /// Run fuzzy comparison across all size-groups that already failed exact
 /// hashing (or were never hashed when -Z is used standalone).
-/// Groups files from both folders by size, then for every cross-folder pair
-/// within each size bucket runs chunked_fuzzy_compare.
-/// Returns (fuzzy_matches, exceeded_pairs_for_debug).
+/// With -A/-E the groups are spread over worker threads like
+/// async_content_compare_with_file_progress (same split_into_chunks HDD
+/// limit); otherwise they run on the current thread. Progress is counted
+/// in compared pairs.
 fn fuzzy_compare_groups(
     groups: &[Vec<FileInfo>],
     options: &CompareOptions,
 ) -> io::Result<Vec<DuplicateGroup>> {
+    let total_pairs: u64 = groups.iter().map(|g| fuzzy_pair_count(g, options)).sum();
+    let progress = ProgressBar::new(total_pairs);
+    progress.set_style(ProgressStyle::default_bar()
+        .template("[{elapsed_precise}] {bar:40.magenta/blue} {pos}/{len} fuzzy pairs ({eta})")
+        .map_err(io::Error::other)?);
+
     let mut results = Vec::new();
 
-    for group in groups {
-        // Collect files from each folder separately
-        let folder0: Vec<&FileInfo> = group.iter().filter(|f| f.folder_index == 0).collect();
-        let folder1: Vec<&FileInfo> = group.iter().filter(|f| f.folder_index == 1).collect();
-
-        // Cross-product: every f0 against every f1 of the same size
-        for f0 in &folder0 {
-            for f1 in &folder1 {
-                let diff = match chunked_fuzzy_compare(&f0.path, &f1.path, options.debug) {
-                    Ok(d) => d,
-                    Err(e) => {
-                        if options.debug {
-                            eprintln!(
-                                "[FUZZY] Error comparing {} <-> {}: {}",
-                                f0.path.display(), f1.path.display(), e
-                            );
-                        }
-                        continue;
-                    }
-                };
+    if options.async_compare || options.enhanced_async {
+        let num_threads = num_cpus::get();
+        let chunks = split_into_chunks(groups.to_vec(), num_threads, options.hdd_optimized);
 
-                if diff <= options.tolerance {
-                    if options.debug {
-                        println!(
-                            "[FUZZY MATCH] {} bytes differ (≤{} threshold)\n  {} <-> {}",
-                            diff, options.tolerance,
-                            f0.path.display(), f1.path.display()
-                        );
-                    }
-                    let files_by_folder = vec![
-                        vec![f0.path.clone()],
-                        vec![f1.path.clone()],
-                    ];
-                    results.push(DuplicateGroup {
-                        files_by_folder,
-                        size: f0.size,
-                        match_kind: MatchKind::Fuzzy { bytes_differing: diff },
-                    });
-                } else if options.debug {
-                    println!(
-                        "[FUZZY EXCEED] {} bytes differ (>{} threshold) — skipped\n  {} <-> {}",
-                        diff, options.tolerance,
-                        f0.path.display(), f1.path.display()
-                    );
-                }
-            }
+        let mut handles = Vec::new();
+        for chunk in chunks {
+            let progress = progress.clone();
+            let options = options.clone();
+            handles.push(thread::spawn(move || {
+                chunk
+                    .iter()
+                    .flat_map(|group| fuzzy_compare_group(group, &options, &progress))
+                    .collect::<Vec<_>>()
+            }));
+        }
+
+        // Joined in chunk order, so the output order doesn't depend on timing
+        for handle in handles {
+            results.extend(handle.join().unwrap());
+        }
+    } else {
+        for group in groups {
+            results.extend(fuzzy_compare_group(group, options, &progress));
         }
     }
 
+    progress.finish();
     Ok(results)
 }
 
+/// Number of pairs fuzzy_compare_group compares up front in one group.
+fn fuzzy_pair_count(group: &[FileInfo], options: &CompareOptions) -> u64 {
+    if options.intra_folder {
+        let n = group.len() as u64;
+        n * n.saturating_sub(1) / 2
+    } else {
+        let in_folder1 = group.iter().filter(|f| f.folder_index == 0).count() as u64;
+        in_folder1 * (group.len() as u64 - in_folder1)
+    }
+}
+
+/// Fuzzy-compare one same-size group: every cross-folder pair (every pair
+/// with -b/-1) goes through fuzzy_pair_diff, and the matching pairs are
+/// merged into clusters by cluster_near_duplicates — one DuplicateGroup per
+/// cluster.
+fn fuzzy_compare_group(group: &[FileInfo], options: &CompareOptions, progress: &ProgressBar) -> Vec<DuplicateGroup> {
+    let size = group[0].size;
+    let limit = options.fuzzy_limit(size);
+
+    let mut edges = Vec::new();
+    for i in 0..group.len() {
+        for j in i + 1..group.len() {
+            if group[i].folder_index == group[j].folder_index && !options.intra_folder {
+                continue;
+            }
+            if let Some(diff) = fuzzy_pair_diff(&group[i], &group[j], limit, options) {
+                edges.push((diff, i, j));
+            }
+            progress.inc(1);
+        }
+    }
+
+    let clusters = cluster_near_duplicates(edges, |i, j| {
+        fuzzy_pair_diff(&group[i], &group[j], limit, options)
+    });
+
+    let mut results = Vec::new();
+    for cluster in clusters {
+        if options.debug {
+            println!(
+                "[FUZZY CLUSTER] {} files, at most {} bytes differ between any two",
+                cluster.members.len(), cluster.diameter
+            );
+        }
+        let mut files_by_folder = vec![Vec::new(), Vec::new()];
+        for &m in &cluster.members {
+            files_by_folder[group[m].folder_index].push(group[m].path.clone());
+        }
+        results.push(DuplicateGroup {
+            files_by_folder,
+            size,
+            match_kind: MatchKind::Fuzzy { bytes_differing: cluster.diameter, reason: None },
+        });
+    }
+    results
+}
+
+/// Byte-level fuzzy comparison of one same-size pair. Returns the number of
+/// differing bytes if it is within `limit`, None if it is over the limit or
+/// the files could not be read.
+fn fuzzy_pair_diff(f0: &FileInfo, f1: &FileInfo, limit: u64, options: &CompareOptions) -> Option<u64> {
+    // The exact count is only worth a full read when someone will see it
+    let exact_count = options.exact_diff_count || options.debug;
+
+    if !exact_count {
+        match fuzzy_probe_rejects(&f0.path, &f1.path, f0.size, limit) {
+            Ok(false) => {}
+            Ok(true) | Err(_) => return None,
+        }
+    }
+
+    let stop_above = if exact_count { None } else { Some(limit) };
+    let diff = match chunked_fuzzy_compare(&f0.path, &f1.path, stop_above, options.debug) {
+        Ok(d) => d,
+        Err(e) => {
+            if options.debug {
+                eprintln!(
+                    "[FUZZY] Error comparing {} <-> {}: {}",
+                    f0.path.display(), f1.path.display(), e
+                );
+            }
+            return None;
+        }
+    };
+
+    if diff <= limit {
+        if options.debug {
+            println!(
+                "[FUZZY MATCH] {} bytes differ (≤{} threshold)\n  {} <-> {}",
+                diff, limit,
+                f0.path.display(), f1.path.display()
+            );
+        }
+        Some(diff)
+    } else {
+        if options.debug {
+            println!(
+                "[FUZZY EXCEED] {} bytes differ (>{} threshold) — skipped\n  {} <-> {}",
+                diff, limit,
+                f0.path.display(), f1.path.display()
+            );
+        }
+        None
+    }
+}
+
 fn split_into_chunks<T: Clone>(items: Vec<T>, chunk_count: usize, hdd_optimized: bool) -> Vec<Vec<T>> {
     let mut chunks = Vec::new();
     let max_chunks = if hdd_optimized { chunk_count.min(4) } else { chunk_count }; // Limit threads for HDDs
@@ -1336,7 +5440,7 @@ fn process_groups(
         let file_size = group[0].size;
 
         for file in &group {
-            match calculate_file_hash(file, options.quick_content_check)? {
+            match calculate_file_hash(file, options.quick_content_check.then_some(&options.quick_sampling))? {
                 Some(hash) => {
                     content_groups.entry(hash).or_default().push(file.clone());
                 },
@@ -1372,6 +5476,89 @@ fn process_groups(
     Ok(())
 }
 
+/// --merge: copy every folder1 file whose content exists nowhere in folder2
+/// to the same relative path in folder2, using the verified copy. What is
+/// "missing" is decided by content, not by path: a file that folder2 holds
+/// under another name is not copied again. A same-path file with other
+/// content in folder2 goes through the --on-collision policy. folder1's own
+/// `deleted/` and `differ/` folders are never merged.
+fn merge_into_folder2(
+    scan_index: &HashMap<PathBuf, FileInfo>,
+    duplicates: &[DuplicateGroup],
+    folder1: &str,
+    folder2: &str,
+    options: &CompareOptions,
+) -> io::Result<()> {
+    let content_ids = assign_content_ids(scan_index, duplicates, options.trust_quick);
+    let folder2_content: HashSet<u64> = scan_index
+        .values()
+        .filter(|f| f.folder_index == 1)
+        .map(|f| content_ids[f.path.as_path()])
+        .collect();
+
+    let quarantine = [Path::new(folder1).join("deleted"), Path::new(folder1).join("differ")];
+    let mut missing: Vec<&FileInfo> = scan_index
+        .values()
+        .filter(|f| f.folder_index == 0)
+        .filter(|f| !folder2_content.contains(&content_ids[f.path.as_path()]))
+        .filter(|f| !quarantine.iter().any(|q| f.path.starts_with(q)))
+        .collect();
+    missing.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let total_size: u64 = missing.iter().map(|f| f.size).sum();
+    println!("Merging {} file(s) ({}) missing from {}...", missing.len(), format_size(total_size), folder2);
+
+    let mut copied = 0usize;
+    let mut changed_count = 0usize;
+    for file in missing {
+        let relative = match file.path.strip_prefix(folder1) {
+            Ok(p) => p,
+            Err(e) => {
+                if options.debug {
+                    eprintln!("Error stripping prefix for {}: {}", file.path.display(), e);
+                }
+                continue;
+            }
+        };
+
+        if let Some(reason) = check_unchanged_since_scan(&file.path, scan_index) {
+            println!("Refused (changed since scan: {}): {}", reason, file.path.display());
+            changed_count += 1;
+            continue;
+        }
+
+        let target = match resolve_collision(sanitize_path(Path::new(folder2).join(relative)), options)? {
+            Some(t) => t,
+            None => continue,
+        };
+
+        if let Some(parent) = target.parent() {
+            if let Err(e) = create_dir_all(parent) {
+                eprintln!("Failed to create directory {}: {}", parent.display(), e);
+                continue;
+            }
+        }
+
+        if options.debug {
+            println!("Copying: {} -> {}", file.path.display(), target.display());
+        }
+        match verified_copy(&file.path, &target, options) {
+            Ok(()) => {
+                println!("Copied: {} -> {}", file.path.display(), target.display());
+                copied += 1;
+            }
+            Err(e) => eprintln!("Failed to copy {} to {}: {}", file.path.display(), target.display(), e),
+        }
+    }
+
+    println!("Merged {} file(s) into {}", copied, folder2);
+    if changed_count > 0 {
+        println!("{} file(s) refused: changed since scan", changed_count);
+    }
+
+    Ok(())
+}
+
 /// Move fuzzy-matched files from folder1 into a `differ/` subfolder,
 /// preserving relative paths. Respects the same -D/-F semantics as
 /// move_duplicates_to_deleted:
@@ -1382,9 +5569,10 @@ fn move_fuzzy_to_differ(
     fuzzy_groups: &[DuplicateGroup],
     folder1: &str,
     folder2: &str,
+    scan_index: &HashMap<PathBuf, FileInfo>,
     options: &CompareOptions,
 ) -> io::Result<()> {
-    let differ_folder = sanitize_path(&Path::new(folder1).join("differ"));
+    let differ_folder = sanitize_path(Path::new(folder1).join("differ"));
     if !differ_folder.exists() {
         if options.debug {
             println!("Creating 'differ' folder at: {}", differ_folder.display());
@@ -1392,13 +5580,20 @@ fn move_fuzzy_to_differ(
         create_dir_all(&differ_folder)?;
     }
 
+    let mut changed_count = 0usize;
+
     for group in fuzzy_groups {
-        let bytes_diff = match &group.match_kind {
-            MatchKind::Fuzzy { bytes_differing } => *bytes_differing,
-            _ => 0,
+        let diff_note = group.match_kind.fuzzy_note();
+
+        // One member of every cluster stays where it is: a folder2 file if
+        // the cluster has one (folder2 is never touched), otherwise the
+        // first folder1 file by path. Every other folder1 file moves.
+        let keeper = match group.files_by_folder[1].iter().min().or_else(|| group.files_by_folder[0].iter().min()) {
+            Some(k) => k,
+            None => continue,
         };
 
-        for file_path in &group.files_by_folder[0] {
+        for file_path in group.files_by_folder[0].iter().filter(|p| *p != keeper) {
             let sanitized = sanitize_path(file_path);
             let relative = match sanitized.strip_prefix(sanitize_path(folder1)) {
                 Ok(p) => p,
@@ -1413,7 +5608,7 @@ fn move_fuzzy_to_differ(
             // -D: only move if the counterpart exists at the same relative
             // path in folder2. -F or single-mode: skip this check.
             if !options.force_delete && !folder2.is_empty() {
-                let corresponding = sanitize_path(&Path::new(folder2).join(relative));
+                let corresponding = sanitize_path(Path::new(folder2).join(relative));
                 if !corresponding.exists() {
                     if options.debug {
                         println!(
@@ -1425,12 +5620,23 @@ fn move_fuzzy_to_differ(
                 }
             }
 
-            let target = sanitize_path(&differ_folder.join(relative));
+            let refused = check_unchanged_since_scan(file_path, scan_index)
+                .or_else(|| check_unchanged_since_scan(keeper, scan_index).map(|r| format!("kept copy {}: {}", keeper.display(), r)));
+            if let Some(reason) = refused {
+                println!("Refused (changed since scan: {}): {}", reason, sanitized.display());
+                changed_count += 1;
+                continue;
+            }
+
+            let target = match resolve_collision(sanitize_path(differ_folder.join(relative)), options)? {
+                Some(t) => t,
+                None => continue,
+            };
 
             if options.debug {
                 println!(
-                    "[FUZZY] Moving ({} bytes differ): {} -> {}",
-                    bytes_diff, sanitized.display(), target.display()
+                    "[FUZZY] Moving ({}): {} -> {}",
+                    diff_note, sanitized.display(), target.display()
                 );
             }
 
@@ -1451,8 +5657,8 @@ fn move_fuzzy_to_differ(
             match rename(&sanitized, &target) {
                 Ok(_) => {
                     println!(
-                        "Moved to differ/ ({} bytes differ): {}",
-                        bytes_diff, sanitized.display()
+                        "Moved to differ/ ({}): {}",
+                        diff_note, sanitized.display()
                     );
                 }
                 Err(_) => {
@@ -1463,8 +5669,8 @@ fn move_fuzzy_to_differ(
                         );
                     } else {
                         println!(
-                            "Moved to differ/ ({} bytes differ): {}",
-                            bytes_diff, sanitized.display()
+                            "Moved to differ/ ({}): {}",
+                            diff_note, sanitized.display()
                         );
                     }
                 }
@@ -1472,6 +5678,10 @@ fn move_fuzzy_to_differ(
         }
     }
 
+    if changed_count > 0 {
+        println!("{} file(s) refused: changed since scan", changed_count);
+    }
+
     Ok(())
 }
 
@@ -1479,6 +5689,7 @@ fn move_duplicates_to_deleted(
     duplicates: &[DuplicateGroup],
     folder1: &str,
     folder2: &str,
+    scan_index: &HashMap<PathBuf, FileInfo>,
     options: &CompareOptions,
 ) -> io::Result<()> {
     let deleted_folder = sanitize_path(&Path::new(folder1).join("deleted"));
@@ -1489,6 +5700,8 @@ fn move_duplicates_to_deleted(
         create_dir_all(&deleted_folder)?;
     }
 
+    let mut changed_count = 0usize;
+
     for duplicate in duplicates {
         for file_path in &duplicate.files_by_folder[0] {
             let sanitized_file_path = sanitize_path(file_path);
@@ -1521,6 +5734,20 @@ fn move_duplicates_to_deleted(
                 }
             }
 
+            // The file (or everything it matched) may have been modified or
+            // replaced since it was hashed — possibly hours ago on big trees.
+            if let Some(reason) = revalidate_before_move(file_path, duplicate, scan_index, options) {
+                println!("Refused (changed since scan: {}): {}", reason, sanitized_file_path.display());
+                changed_count += 1;
+                continue;
+            }
+
+            // Never overwrite something already quarantined by an earlier run
+            let target_path = match resolve_collision(target_path, options)? {
+                Some(path) => path,
+                None => continue,
+            };
+
             // Create parent directories in the "deleted" folder
             if let Some(parent) = target_path.parent() {
                 if options.debug {
@@ -1578,6 +5805,198 @@ fn move_duplicates_to_deleted(
         }
     }
 
+    if changed_count > 0 {
+        println!("{} file(s) refused: changed since scan", changed_count);
+    }
+
+    Ok(())
+}
+
+/// --dirs with -D/-F: move whole folder1 directories into `deleted/` when
+/// they are identical to, or contained in, a directory that stays behind.
+/// Every file inside the directory (and its counterpart) is re-validated
+/// first; a directory with any changed, missing or new file is refused.
+/// In single-folder mode the first directory of an identical set is kept.
+fn move_dirs_to_deleted(
+    dir_groups: &[DirDuplicateGroup],
+    folder1: &str,
+    folder2: &str,
+    scan_index: &HashMap<PathBuf, FileInfo>,
+    options: &CompareOptions,
+) -> io::Result<HashSet<PathBuf>> {
+    let deleted_folder = sanitize_path(Path::new(folder1).join("deleted"));
+    if !deleted_folder.exists() {
+        if options.debug {
+            println!("Creating 'deleted' folder at: {}", deleted_folder.display());
+        }
+        create_dir_all(&deleted_folder)?;
+    }
+
+    let mut changed_count = 0usize;
+    let mut moved = HashSet::new();
+
+    for group in dir_groups {
+        if matches!(group.kind, DirMatchKind::Folder2Subset { .. }) {
+            continue; // folder1 has files folder2 lacks: nothing safe to move
+        }
+
+        let (to_move, keepers): (Vec<&PathBuf>, Vec<&PathBuf>) = if group.dirs_by_folder[1].is_empty() {
+            match group.dirs_by_folder[0].split_first() {
+                Some((first, rest)) => (rest.iter().collect(), vec![first]),
+                None => continue,
+            }
+        } else {
+            (group.dirs_by_folder[0].iter().collect(), group.dirs_by_folder[1].iter().collect())
+        };
+
+        for dir in to_move {
+            let sanitized_dir = sanitize_path(dir);
+            // Already quarantined by an earlier run: never move it again
+            if sanitized_dir.starts_with(&deleted_folder)
+                || sanitized_dir.starts_with(sanitize_path(Path::new(folder1).join("differ"))) {
+                continue;
+            }
+            let relative = match sanitized_dir.strip_prefix(sanitize_path(folder1)) {
+                Ok(p) => p.to_path_buf(),
+                Err(e) => {
+                    if options.debug {
+                        eprintln!("Error stripping prefix for {}: {}", sanitized_dir.display(), e);
+                    }
+                    continue;
+                }
+            };
+
+            if !options.force_delete && !folder2.is_empty() {
+                let corresponding = sanitize_path(Path::new(folder2).join(&relative));
+                if !corresponding.is_dir() {
+                    if options.debug {
+                        println!("Directory does not exist in folder2: {}", corresponding.display());
+                        println!("Skipping directory (use -F to force delete regardless of folder2 path)");
+                    }
+                    continue;
+                }
+            }
+
+            let refused = keepers
+                .iter()
+                .map(|keeper| revalidate_dir_before_move(dir, keeper, scan_index, options))
+                .collect::<Vec<_>>();
+            if refused.iter().all(|r| r.is_some()) {
+                let reason = refused.into_iter().flatten().next().unwrap_or_default();
+                println!("Refused (changed since scan: {}): {}", reason, sanitized_dir.display());
+                changed_count += 1;
+                continue;
+            }
+
+            let target = match resolve_collision(sanitize_path(deleted_folder.join(&relative)), options)? {
+                Some(t) => t,
+                None => continue,
+            };
+
+            if let Some(parent) = target.parent() {
+                if let Err(e) = create_dir_all(parent) {
+                    eprintln!("Failed to create directory {}: {}", parent.display(), e);
+                    continue;
+                }
+            }
+
+            match rename(dir, &target) {
+                Ok(_) => {
+                    if options.debug {
+                        println!("Directory moved successfully using rename()");
+                    }
+                    println!("Moved directory: {}", sanitized_dir.display());
+                    moved.insert(dir.clone());
+                }
+                Err(e) => {
+                    if options.debug {
+                        eprintln!("Rename failed: {} -> {}: {}; falling back to per-file copy+remove",
+                            dir.display(), target.display(), e);
+                    }
+                    match move_dir_by_copy(dir, &target, options) {
+                        Ok(()) => {
+                            println!("Moved directory: {}", sanitized_dir.display());
+                            moved.insert(dir.clone());
+                        }
+                        Err(e) => eprintln!("Failed to move directory {} to {}: {}",
+                            sanitized_dir.display(), target.display(), e),
+                    }
+                }
+            }
+        }
+    }
+
+    if changed_count > 0 {
+        println!("{} directory(ies) refused: changed since scan", changed_count);
+    }
+
+    Ok(moved)
+}
+
+/// Check that `dir` still holds exactly the files recorded at scan time, all
+/// unchanged, and that each has an unchanged counterpart at the same relative
+/// path under `keeper` (re-hashed and compared with --rehash).
+/// Returns the reason if the directory must not be moved.
+fn revalidate_dir_before_move(
+    dir: &Path,
+    keeper: &Path,
+    scan_index: &HashMap<PathBuf, FileInfo>,
+    options: &CompareOptions,
+) -> Option<String> {
+    let recorded = scan_index.keys().filter(|p| p.starts_with(dir)).count();
+    let mut seen = 0usize;
+    for entry in WalkDir::new(dir).into_iter() {
+        let entry = match entry {
+            Ok(e) => e,
+            Err(e) => return Some(format!("cannot walk directory: {}", e)),
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let path = entry.path();
+        seen += 1;
+        if let Some(reason) = check_unchanged_since_scan(path, scan_index) {
+            return Some(format!("{}: {}", path.display(), reason));
+        }
+        let relative = path.strip_prefix(dir).unwrap_or(path);
+        let counterpart = keeper.join(relative);
+        if let Some(reason) = check_unchanged_since_scan(&counterpart, scan_index) {
+            return Some(format!("{}: {}", counterpart.display(), reason));
+        }
+        if options.rehash_before_move {
+            let a = calculate_file_hash(&scan_index[path], None).ok().flatten();
+            let b = calculate_file_hash(&scan_index[counterpart.as_path()], None).ok().flatten();
+            if a.is_none() || a != b {
+                return Some(format!("{}: content no longer matches", path.display()));
+            }
+        }
+    }
+    if seen != recorded {
+        return Some(format!("{} file(s) at scan, {} now", recorded, seen));
+    }
+    None
+}
+
+/// Fallback for directory moves that rename() can't do (e.g. cross-device):
+/// move every file with the verified copy+remove, then drop the emptied
+/// source directories. Stops at the first failure, leaving the rest in place.
+fn move_dir_by_copy(src: &Path, dst: &Path, options: &CompareOptions) -> io::Result<()> {
+    let mut dirs = Vec::new();
+    for entry in WalkDir::new(src).into_iter() {
+        let entry = entry.map_err(io::Error::other)?;
+        let relative = entry.path().strip_prefix(src).unwrap_or(entry.path());
+        let target = dst.join(relative);
+        if entry.file_type().is_dir() {
+            create_dir_all(&target)?;
+            dirs.push(entry.path().to_path_buf());
+        } else {
+            copy_and_remove(entry.path(), &target, options)?;
+        }
+    }
+    // Deepest first; remove_dir only succeeds on empty directories
+    for dir in dirs.iter().rev() {
+        std::fs::remove_dir(dir)?;
+    }
     Ok(())
 }
 
@@ -1603,11 +6022,136 @@ fn sanitize_path<P: AsRef<Path>>(path: P) -> PathBuf {
     sanitized
 }
 
+/// Re-check a file against the size and mtime `collect_files` recorded for it.
+/// Returns the reason if the file is gone, was never scanned, or changed.
+fn check_unchanged_since_scan(path: &Path, scan_index: &HashMap<PathBuf, FileInfo>) -> Option<String> {
+    let recorded = match scan_index.get(path) {
+        Some(info) => info,
+        None => return Some("not in scan".to_string()),
+    };
+    let metadata = match std::fs::metadata(path) {
+        Ok(m) => m,
+        Err(_) => return Some("missing".to_string()),
+    };
+    if metadata.len() != recorded.size {
+        return Some(format!("size {} -> {}", recorded.size, metadata.len()));
+    }
+    if metadata.modified().ok() != recorded.mtime {
+        return Some("mtime changed".to_string());
+    }
+    None
+}
+
+/// Make sure a folder1 file is still safe to act on: it must be unchanged
+/// since scan, and so must at least one of the files it was matched with
+/// (otherwise the "other copy" may no longer exist). With --rehash, exact
+/// matches are also re-hashed and must still agree with that counterpart.
+/// Returns the reason if the move must be refused.
+fn revalidate_before_move(
+    file_path: &Path,
+    group: &DuplicateGroup,
+    scan_index: &HashMap<PathBuf, FileInfo>,
+    options: &CompareOptions,
+) -> Option<String> {
+    if let Some(reason) = check_unchanged_since_scan(file_path, scan_index) {
+        return Some(reason);
+    }
+
+    let counterparts: Vec<&PathBuf> = group.files_by_folder[1]
+        .iter()
+        .chain(group.files_by_folder[0].iter())
+        .filter(|p| p.as_path() != file_path)
+        .filter(|p| check_unchanged_since_scan(p, scan_index).is_none())
+        .collect();
+    if counterparts.is_empty() {
+        return Some("no unchanged counterpart left".to_string());
+    }
+
+    if options.rehash_before_move && group.match_kind == MatchKind::Exact {
+        let source_hash = match calculate_file_hash(&scan_index[file_path], None) {
+            Ok(h) => h,
+            Err(e) => return Some(format!("re-hash failed: {}", e)),
+        };
+        for counterpart in counterparts {
+            if let Ok(hash) = calculate_file_hash(&scan_index[counterpart.as_path()], None) {
+                if hash == source_hash {
+                    if options.debug {
+                        println!("Re-hash confirmed against {}", counterpart.display());
+                    }
+                    return None;
+                }
+            }
+        }
+        return Some("content no longer matches".to_string());
+    }
+
+    None
+}
+
+/// Apply the --on-collision policy to a move target.
+/// Returns the path to move to, or None if the file should be left in place.
+/// Never returns a path that already exists.
+fn resolve_collision(target: PathBuf, options: &CompareOptions) -> io::Result<Option<PathBuf>> {
+    if !target.exists() {
+        return Ok(Some(target));
+    }
+
+    match options.on_collision {
+        CollisionPolicy::Skip => {
+            println!("Skipped (target already exists): {}", target.display());
+            Ok(None)
+        }
+        CollisionPolicy::Fail => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("Target already exists: {} (use --on-collision suffix|skip)", target.display()),
+        )),
+        CollisionPolicy::Suffix => {
+            let stem = target.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            let ext = target.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+            let mut counter = 1u32;
+            loop {
+                let candidate = target.with_file_name(format!("{} ({}){}", stem, counter, ext));
+                if !candidate.exists() {
+                    if options.debug {
+                        println!("Target exists, using suffixed name: {}", candidate.display());
+                    }
+                    return Ok(Some(candidate));
+                }
+                counter += 1;
+            }
+        }
+    }
+}
+
 fn copy_and_remove(src: &Path, dst: &Path, options: &CompareOptions) -> io::Result<()> {
     if options.debug {
         println!("Copying file as fallback: {} -> {}", src.display(), dst.display());
     }
 
+    verified_copy(src, dst, options)?;
+
+    // Only now, after all verifications pass, remove the original file
+    if options.debug {
+        println!("Verification complete, removing original file: {}", src.display());
+    }
+    std::fs::remove_file(src)?;
+
+    Ok(())
+}
+
+/// Copy `src` to `dst`, verify the copy is complete and preserve permissions
+/// and timestamps. On a failed verification the partial destination is
+/// removed; the source is never touched.
+fn verified_copy(src: &Path, dst: &Path, options: &CompareOptions) -> io::Result<()> {
+    // Never clobber an existing file: it may be the only remaining copy
+    // from an earlier run. The caller is expected to pick a free name.
+    if dst.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("Destination already exists, refusing to overwrite: {}", dst.display()),
+        ));
+    }
+
     // Get source metadata before copying (for verification and preservation)
     let src_metadata = src.metadata()?;
     let src_size = src_metadata.len();
@@ -1680,12 +6224,6 @@ fn copy_and_remove(src: &Path, dst: &Path, options: &CompareOptions) -> io::Resu
         }
     }
 
-    // Only now, after all verifications pass, remove the original file
-    if options.debug {
-        println!("Verification complete, removing original file: {}", src.display());
-    }
-    std::fs::remove_file(src)?;
-
     Ok(())
 }
 
@@ -1725,10 +6263,16 @@ fn display_results(duplicates: &[DuplicateGroup], folder1: &str, folder2: &str)
     }
 
     let exact_count = duplicates.iter().filter(|g| g.match_kind == MatchKind::Exact).count();
-    let fuzzy_count = duplicates.len() - exact_count;
+    let quick_count = duplicates.iter().filter(|g| g.match_kind == MatchKind::QuickMatch).count();
+    let fuzzy_count = duplicates.len() - exact_count - quick_count;
 
-    println!("\nFound {} duplicate group(s) ({} exact, {} fuzzy near-duplicate):",
-        duplicates.len(), exact_count, fuzzy_count);
+    if quick_count > 0 {
+        println!("\nFound {} duplicate group(s) ({} exact, {} quick-check only, {} fuzzy near-duplicate):",
+            duplicates.len(), exact_count, quick_count, fuzzy_count);
+    } else {
+        println!("\nFound {} duplicate group(s) ({} exact, {} fuzzy near-duplicate):",
+            duplicates.len(), exact_count, fuzzy_count);
+    }
     println!("{:40} : {:40} | {:10} | {}", folder1, folder2, "size", "match");
     println!("{}", "-".repeat(100));
     
@@ -1743,7 +6287,15 @@ fn display_results(duplicates: &[DuplicateGroup], folder1: &str, folder2: &str)
 
         let match_label = match &duplicate.match_kind {
             MatchKind::Exact => "EXACT".to_string(),
-            MatchKind::Fuzzy { bytes_differing } => format!("FUZZY ~{}B", bytes_differing),
+            MatchKind::QuickMatch => "QUICK".to_string(),
+            MatchKind::Fuzzy { reason: Some(reason), .. } => format!("FUZZY ({})", reason),
+            MatchKind::Fuzzy { bytes_differing, .. } => format!(
+                "FUZZY ~{}B ({:.3}%)",
+                bytes_differing,
+                if duplicate.size > 0 { *bytes_differing as f64 * 100.0 / duplicate.size as f64 } else { 0.0 }
+            ),
+            MatchKind::Perceptual { distance } => format!("PERCEPTUAL (distance {})", distance),
+            MatchKind::TextNormalized => "TEXT-NORMALIZED".to_string(),
         };
         
         total_folder1_files += folder1_files.len();
@@ -1791,6 +6343,141 @@ fn display_results(duplicates: &[DuplicateGroup], folder1: &str, folder2: &str)
     println!("Total duplicates size (from 1st folder): {}", format_size(total_duplicate_size_folder1));
 }
 
+fn display_diff_results(entries: &[DiffEntry], folder1: &str, folder2: &str, format: OutputFormat) {
+    let relative_list = |paths: &[PathBuf], base: &str| {
+        paths.iter()
+            .map(|p| p.strip_prefix(base).unwrap_or(p).to_string_lossy().to_string())
+            .collect::<Vec<_>>()
+    };
+    let status_label = |status: DiffStatus| match status {
+        DiffStatus::OnlyInFolder1 => "ONLY IN 1",
+        DiffStatus::OnlyInFolder2 => "ONLY IN 2",
+        DiffStatus::Moved => "MOVED",
+        DiffStatus::Changed => "CHANGED",
+    };
+
+    if format == OutputFormat::Tsv {
+        // One row per (folder1, folder2) path pair; an absent side is empty
+        println!("status\tfolder1\tfolder2\tsize");
+        for entry in entries {
+            let left = relative_list(&entry.folder1_paths, folder1);
+            let right = relative_list(&entry.folder2_paths, folder2);
+            let left = if left.is_empty() { vec![String::new()] } else { left };
+            let right = if right.is_empty() { vec![String::new()] } else { right };
+            for l in &left {
+                for r in &right {
+                    println!("{}\t{}\t{}\t{}", status_label(entry.status), l, r, entry.size);
+                }
+            }
+        }
+        return;
+    }
+
+    if entries.is_empty() {
+        println!("Folders have identical content.");
+        return;
+    }
+
+    let count = |status: DiffStatus| entries.iter().filter(|e| e.status == status).count();
+    println!("\nFound {} difference(s) ({} only in folder1, {} only in folder2, {} moved, {} changed):",
+        entries.len(),
+        count(DiffStatus::OnlyInFolder1),
+        count(DiffStatus::OnlyInFolder2),
+        count(DiffStatus::Moved),
+        count(DiffStatus::Changed));
+    println!("{:40} : {:40} | {:10} | status", folder1, folder2, "size");
+    println!("{}", "-".repeat(100));
+
+    let mut missing_size = 0u64;
+    for entry in entries {
+        if entry.status == DiffStatus::OnlyInFolder1 {
+            missing_size += entry.size;
+        }
+        println!("{:40} : {:40} | {:10} | {}",
+            relative_list(&entry.folder1_paths, folder1).join("; "),
+            relative_list(&entry.folder2_paths, folder2).join("; "),
+            format_size(entry.size),
+            status_label(entry.status));
+    }
+
+    println!("{}", "-".repeat(100));
+    println!("Missing from folder2 (only in folder1): {}", format_size(missing_size));
+}
+
+fn display_rename_results(pairs: &[RenamePair], folder1: &str, folder2: &str, format: OutputFormat) {
+    let kind_label = |kind: RenameKind| match kind {
+        RenameKind::Renamed => "RENAMED",
+        RenameKind::Moved => "MOVED",
+        RenameKind::MovedAndRenamed => "MOVED+RENAMED",
+    };
+    let relative = |p: &Path, base: &str| p.strip_prefix(base).unwrap_or(p).to_string_lossy().to_string();
+
+    if format == OutputFormat::Tsv {
+        println!("kind\tfolder1\tfolder2\tsize");
+        for pair in pairs {
+            println!("{}\t{}\t{}\t{}", kind_label(pair.kind),
+                relative(&pair.folder1_path, folder1), relative(&pair.folder2_path, folder2), pair.size);
+        }
+        return;
+    }
+
+    if pairs.is_empty() {
+        println!("No renamed or moved files found.");
+        return;
+    }
+
+    let count = |kind: RenameKind| pairs.iter().filter(|p| p.kind == kind).count();
+    println!("\nFound {} reorganized file(s) ({} renamed, {} moved, {} moved+renamed):",
+        pairs.len(), count(RenameKind::Renamed), count(RenameKind::Moved), count(RenameKind::MovedAndRenamed));
+    println!("{:40} : {:40} | {:10} | change", folder1, folder2, "size");
+    println!("{}", "-".repeat(100));
+    for pair in pairs {
+        println!("{:40} : {:40} | {:10} | {}",
+            relative(&pair.folder1_path, folder1),
+            relative(&pair.folder2_path, folder2),
+            format_size(pair.size),
+            kind_label(pair.kind));
+    }
+    println!("{}", "-".repeat(100));
+}
+
+fn display_dir_results(dir_groups: &[DirDuplicateGroup], folder1: &str, folder2: &str) {
+    if dir_groups.is_empty() {
+        println!("No duplicate directories found.");
+        return;
+    }
+
+    let identical_count = dir_groups.iter().filter(|g| g.kind == DirMatchKind::Identical).count();
+    let subset_count = dir_groups.len() - identical_count;
+
+    println!("\nFound {} duplicate directory group(s) ({} identical, {} subset):",
+        dir_groups.len(), identical_count, subset_count);
+    println!("{:40} : {:40} | {:10} | {:8} | match", folder1, folder2, "size", "files");
+    println!("{}", "-".repeat(100));
+
+    for group in dir_groups {
+        let side_text = |dirs: &[PathBuf], base: &str| {
+            dirs.iter()
+                .map(|p| format!("{}/", p.strip_prefix(base).unwrap_or(p).to_string_lossy()))
+                .collect::<Vec<_>>()
+                .join("; ")
+        };
+        let match_label = match &group.kind {
+            DirMatchKind::Identical => "IDENTICAL".to_string(),
+            DirMatchKind::Folder1Subset { extra_files } => format!("SUBSET (folder2 has +{} files)", extra_files),
+            DirMatchKind::Folder2Subset { extra_files } => format!("SUBSET (folder1 has +{} files)", extra_files),
+        };
+        println!("{:40} : {:40} | {:10} | {:8} | {}",
+            side_text(&group.dirs_by_folder[0], folder1),
+            side_text(&group.dirs_by_folder[1], folder2),
+            format_size(group.size),
+            group.file_count,
+            match_label);
+    }
+
+    println!("{}", "-".repeat(100));
+}
+
 fn display_subdirectory_details(
     folder1_files: &[PathBuf], 
     folder2_files: &[PathBuf],
@@ -1853,4 +6540,691 @@ fn display_subdirectory_details(
             );
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU64;
+
+    /// A scratch directory under the system temp dir, removed on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            static COUNTER: AtomicU64 = AtomicU64::new(0);
+            let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+            let path = std::env::temp_dir().join(format!("duptool-test-{}-{}-{}", name, std::process::id(), n));
+            create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+
+        fn write(&self, name: &str, content: &[u8]) -> PathBuf {
+            let path = self.0.join(name);
+            std::fs::write(&path, content).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Options as parsed from a bare `duptool folder1 folder2`.
+    fn test_options() -> CompareOptions {
+        CompareOptions {
+            compare_content: true,
+            compare_name: false,
+            compare_size: false,
+            quick_content_check: false,
+            everything_name: false,
+            everything_size: false,
+            bidirectional: false,
+            async_compare: false,
+            hdd_optimized: true,
+            delete_duplicates: false,
+            debug: false,
+            enhanced_async: false,
+            force_delete: false,
+            intra_folder: false,
+            fuzzy: false,
+            tolerance: None,
+            tolerance_pct: None,
+            exact_diff_count: false,
+            similarity: None,
+            show_diff: false,
+            perceptual_image: false,
+            perceptual_distance: 8,
+            perceptual_audio: false,
+            audio_similarity: 80.0,
+            text_normalize: false,
+            archives: false,
+            quick_sampling: QuickSampling { sample_size: QUICKCHECK_SIZE as u64, samples: 2, random_seed: None, adaptive: false },
+            trust_quick: false,
+            prefilter: true,
+            device_threads: None,
+            fuzzy_as_dupes: false,
+            format_aware: false,
+            on_collision: CollisionPolicy::Suffix,
+            rehash_before_move: false,
+            dirs: false,
+            diff: false,
+            merge: false,
+            renames: false,
+            rename_script: None,
+            output_format: OutputFormat::Table,
+        }
+    }
+
+    /// An in-memory scan: (path, folder index, size) per file. Nothing is
+    /// read from disk by the functions these feed.
+    fn scan_of(files: &[(&str, usize, u64)]) -> HashMap<PathBuf, FileInfo> {
+        files
+            .iter()
+            .map(|&(path, folder_index, size)| {
+                let info = FileInfo {
+                    path: PathBuf::from(path),
+                    size,
+                    folder_index,
+                    mtime: None,
+                    archive_member: None,
+                    device: 0,
+                };
+                (PathBuf::from(path), info)
+            })
+            .collect()
+    }
+
+    /// An exact group of same-content files; paths under /f2 are folder2's.
+    fn exact(paths: &[&str]) -> DuplicateGroup {
+        let mut files_by_folder = vec![Vec::new(), Vec::new()];
+        for path in paths {
+            files_by_folder[usize::from(path.starts_with("/f2/"))].push(PathBuf::from(path));
+        }
+        DuplicateGroup { files_by_folder, size: 1, match_kind: MatchKind::Exact }
+    }
+
+    #[test]
+    fn collision_free_target_is_used_as_is() {
+        let dir = TempDir::new("collision");
+        let target = dir.0.join("photo.jpg");
+        assert_eq!(resolve_collision(target.clone(), &test_options()).unwrap(), Some(target));
+    }
+
+    #[test]
+    fn collision_suffix_picks_the_next_free_name() {
+        let dir = TempDir::new("collision");
+        let target = dir.write("photo.jpg", b"a");
+        let options = test_options();
+        assert_eq!(resolve_collision(target.clone(), &options).unwrap(), Some(dir.0.join("photo (1).jpg")));
+        dir.write("photo (1).jpg", b"b");
+        assert_eq!(resolve_collision(target, &options).unwrap(), Some(dir.0.join("photo (2).jpg")));
+
+        let bare = dir.write("README", b"c");
+        assert_eq!(resolve_collision(bare, &options).unwrap(), Some(dir.0.join("README (1)")));
+    }
+
+    #[test]
+    fn collision_skip_and_fail_leave_the_file() {
+        let dir = TempDir::new("collision");
+        let target = dir.write("photo.jpg", b"a");
+        let mut options = test_options();
+
+        options.on_collision = CollisionPolicy::Skip;
+        assert_eq!(resolve_collision(target.clone(), &options).unwrap(), None);
+
+        options.on_collision = CollisionPolicy::Fail;
+        let err = resolve_collision(target, &options).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn dirs_identical_subtrees_are_one_group() {
+        let scan = scan_of(&[
+            ("/f1/photos/a.jpg", 0, 10),
+            ("/f1/photos/2020/b.jpg", 0, 20),
+            ("/f2/backup/a.jpg", 1, 10),
+            ("/f2/backup/2020/b.jpg", 1, 20),
+        ]);
+        let groups = [
+            exact(&["/f1/photos/a.jpg", "/f2/backup/a.jpg"]),
+            exact(&["/f1/photos/2020/b.jpg", "/f2/backup/2020/b.jpg"]),
+        ];
+        let dirs = find_duplicate_dirs(&scan, &groups, "/f1", "/f2", &test_options());
+
+        // The 2020 pair is implied by its identical parents
+        assert_eq!(dirs.len(), 1);
+        assert_eq!(dirs[0].kind, DirMatchKind::Identical);
+        assert_eq!(dirs[0].dirs_by_folder, vec![vec![PathBuf::from("/f1/photos")], vec![PathBuf::from("/f2/backup")]]);
+        assert_eq!((dirs[0].file_count, dirs[0].size), (2, 30));
+    }
+
+    #[test]
+    fn dirs_subset_is_not_identical() {
+        let scan = scan_of(&[
+            ("/f1/docs/a.txt", 0, 1),
+            ("/f2/docs/a.txt", 1, 1),
+            ("/f2/docs/b.txt", 1, 2),
+        ]);
+        let groups = [exact(&["/f1/docs/a.txt", "/f2/docs/a.txt"])];
+        let dirs = find_duplicate_dirs(&scan, &groups, "/f1", "/f2", &test_options());
+
+        assert_eq!(dirs.len(), 1);
+        assert_eq!(dirs[0].kind, DirMatchKind::Folder1Subset { extra_files: 1 });
+        assert_eq!(dirs[0].dirs_by_folder, vec![vec![PathBuf::from("/f1/docs")], vec![PathBuf::from("/f2/docs")]]);
+    }
+
+    #[test]
+    fn dirs_same_names_with_other_content_do_not_match() {
+        let scan = scan_of(&[("/f1/docs/a.txt", 0, 1), ("/f2/docs/a.txt", 1, 1)]);
+        assert!(find_duplicate_dirs(&scan, &[], "/f1", "/f2", &test_options()).is_empty());
+    }
+
+    #[test]
+    fn dirs_quick_matches_count_only_when_trusted() {
+        let scan = scan_of(&[("/f1/docs/a.txt", 0, 1), ("/f2/docs/a.txt", 1, 1)]);
+        let mut group = exact(&["/f1/docs/a.txt", "/f2/docs/a.txt"]);
+        group.match_kind = MatchKind::QuickMatch;
+        let mut options = test_options();
+        assert!(find_duplicate_dirs(&scan, std::slice::from_ref(&group), "/f1", "/f2", &options).is_empty());
+
+        options.trust_quick = true;
+        let dirs = find_duplicate_dirs(&scan, &[group], "/f1", "/f2", &options);
+        assert_eq!(dirs.len(), 1);
+        assert_eq!(dirs[0].kind, DirMatchKind::Identical);
+    }
+
+    #[test]
+    fn dirs_move_identical_and_keep_folder2_subsets() {
+        let root = TempDir::new("dirs-move");
+        for dir in ["f1/copy", "f1/more", "f2/copy", "f2/more"] {
+            create_dir_all(root.0.join(dir)).unwrap();
+        }
+        for side in ["f1", "f2"] {
+            root.write(&format!("{}/copy/a.txt", side), b"same");
+            root.write(&format!("{}/more/a.txt", side), b"shared");
+        }
+        root.write("f1/more/b.txt", b"only in folder1");
+        let (folder1, folder2) = (root.0.join("f1"), root.0.join("f2"));
+        let (folder1, folder2) = (folder1.to_str().unwrap(), folder2.to_str().unwrap());
+
+        let mut files = collect_files(folder1, 0, true, false).unwrap();
+        files.extend(collect_files(folder2, 1, true, false).unwrap());
+        let scan: HashMap<PathBuf, FileInfo> = files.into_iter().map(|f| (f.path.clone(), f)).collect();
+        let pair = |name: &str| DuplicateGroup {
+            files_by_folder: vec![vec![Path::new(folder1).join(name)], vec![Path::new(folder2).join(name)]],
+            size: 1,
+            match_kind: MatchKind::Exact,
+        };
+        let groups = [pair("copy/a.txt"), pair("more/a.txt")];
+
+        let mut options = test_options();
+        options.delete_duplicates = true;
+        let dirs = find_duplicate_dirs(&scan, &groups, folder1, folder2, &options);
+        assert_eq!(dirs.len(), 2);
+        let moved = move_dirs_to_deleted(&dirs, folder1, folder2, &scan, &options).unwrap();
+
+        // folder1/more has a file folder2 lacks: it must stay
+        assert_eq!(moved, HashSet::from([Path::new(folder1).join("copy")]));
+        assert!(Path::new(folder1).join("deleted/copy/a.txt").is_file());
+        assert!(Path::new(folder1).join("more/b.txt").is_file());
+        assert!(Path::new(folder2).join("copy/a.txt").is_file());
+    }
+
+    #[test]
+    fn diff_classifies_moved_changed_and_one_sided_files() {
+        let scan = scan_of(&[
+            ("/f1/same.txt", 0, 1),
+            ("/f2/same.txt", 1, 1),
+            ("/f1/changed.txt", 0, 2),
+            ("/f2/changed.txt", 1, 3),
+            ("/f1/old/song.mp3", 0, 4),
+            ("/f2/new/song.mp3", 1, 4),
+            ("/f1/only1.txt", 0, 5),
+            ("/f2/only2.txt", 1, 6),
+        ]);
+        let groups = [
+            exact(&["/f1/same.txt", "/f2/same.txt"]),
+            exact(&["/f1/old/song.mp3", "/f2/new/song.mp3"]),
+        ];
+        let entries = find_folder_diff(&scan, &groups, "/f1", "/f2", false);
+        fn paths(side: &[PathBuf]) -> Vec<&str> {
+            side.iter().map(|p| p.to_str().unwrap()).collect()
+        }
+        let summary: Vec<(DiffStatus, Vec<&str>, Vec<&str>)> = entries
+            .iter()
+            .map(|e| (e.status, paths(&e.folder1_paths), paths(&e.folder2_paths)))
+            .collect();
+
+        assert_eq!(summary, vec![
+            (DiffStatus::OnlyInFolder1, vec!["/f1/only1.txt"], vec![]),
+            (DiffStatus::OnlyInFolder2, vec![], vec!["/f2/only2.txt"]),
+            (DiffStatus::Moved, vec!["/f1/old/song.mp3"], vec!["/f2/new/song.mp3"]),
+            (DiffStatus::Changed, vec!["/f1/changed.txt"], vec!["/f2/changed.txt"]),
+        ]);
+    }
+
+    #[test]
+    fn diff_extra_copy_lists_the_unchanged_original() {
+        let scan = scan_of(&[("/f1/a.txt", 0, 1), ("/f2/a.txt", 1, 1), ("/f2/copy/a.txt", 1, 1)]);
+        let groups = [exact(&["/f1/a.txt", "/f2/a.txt", "/f2/copy/a.txt"])];
+        let entries = find_folder_diff(&scan, &groups, "/f1", "/f2", false);
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].status, DiffStatus::Moved);
+        assert_eq!(entries[0].folder1_paths, vec![PathBuf::from("/f1/a.txt")]);
+        assert_eq!(entries[0].folder2_paths, vec![PathBuf::from("/f2/copy/a.txt")]);
+    }
+
+    #[test]
+    fn renames_are_classified_by_directory_and_name() {
+        let scan = scan_of(&[
+            ("/f1/docs/a.txt", 0, 1),
+            ("/f2/docs/b.txt", 1, 1),
+            ("/f1/x/c.txt", 0, 2),
+            ("/f2/y/c.txt", 1, 2),
+            ("/f1/p/e.txt", 0, 3),
+            ("/f2/q/g.txt", 1, 3),
+            ("/f1/same.txt", 0, 4),
+            ("/f2/same.txt", 1, 4),
+        ]);
+        let groups = [
+            exact(&["/f1/docs/a.txt", "/f2/docs/b.txt"]),
+            exact(&["/f1/x/c.txt", "/f2/y/c.txt"]),
+            exact(&["/f1/p/e.txt", "/f2/q/g.txt"]),
+            exact(&["/f1/same.txt", "/f2/same.txt"]),
+        ];
+        let pairs = find_renames(&scan, &groups, "/f1", "/f2", false);
+        let summary: Vec<(RenameKind, &str, &str)> = pairs
+            .iter()
+            .map(|p| (p.kind, p.folder1_path.to_str().unwrap(), p.folder2_path.to_str().unwrap()))
+            .collect();
+
+        assert_eq!(summary, vec![
+            (RenameKind::Renamed, "/f1/docs/a.txt", "/f2/docs/b.txt"),
+            (RenameKind::Moved, "/f1/x/c.txt", "/f2/y/c.txt"),
+            (RenameKind::MovedAndRenamed, "/f1/p/e.txt", "/f2/q/g.txt"),
+        ]);
+    }
+
+    #[test]
+    fn renames_prefer_a_partner_with_the_same_name() {
+        let scan = scan_of(&[("/f1/new/a.txt", 0, 1), ("/f2/b.txt", 1, 1), ("/f2/old/a.txt", 1, 1)]);
+        let groups = [exact(&["/f1/new/a.txt", "/f2/b.txt", "/f2/old/a.txt"])];
+        let pairs = find_renames(&scan, &groups, "/f1", "/f2", false);
+
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(pairs[0].kind, RenameKind::Moved);
+        assert_eq!(pairs[0].folder2_path, PathBuf::from("/f2/old/a.txt"));
+    }
+
+    fn rename(kind: RenameKind, folder1_path: &str, folder2_path: &str) -> RenamePair {
+        RenamePair { kind, folder1_path: folder1_path.into(), folder2_path: folder2_path.into(), size: 1 }
+    }
+
+    #[test]
+    fn rename_plan_routes_swaps_through_a_temporary_name() {
+        let pairs = [
+            rename(RenameKind::Renamed, "/f1/a", "/f2/b"),
+            rename(RenameKind::Renamed, "/f1/b", "/f2/a"),
+            rename(RenameKind::Renamed, "/f1/c", "/f2/d"),
+        ];
+        let (moves, rerouted) = plan_rename_moves(&pairs, "/f1", "/f2");
+        let moves: Vec<(&str, &str)> = moves.iter().map(|(a, b)| (a.to_str().unwrap(), b.to_str().unwrap())).collect();
+
+        assert_eq!(rerouted.len(), 2);
+        assert_eq!(moves, vec![
+            ("/f2/b", "/f2/b.duptool-rename-0"),
+            ("/f2/a", "/f2/a.duptool-rename-1"),
+            ("/f2/b.duptool-rename-0", "/f2/a"),
+            ("/f2/a.duptool-rename-1", "/f2/b"),
+            ("/f2/d", "/f2/c"),
+        ]);
+    }
+
+    #[test]
+    fn rename_plan_applies_chains_in_full() {
+        // a -> b while b -> c: b has to leave before a arrives
+        let pairs = [
+            rename(RenameKind::Renamed, "/f1/b", "/f2/a"),
+            rename(RenameKind::Renamed, "/f1/c", "/f2/b"),
+        ];
+        let (moves, rerouted) = plan_rename_moves(&pairs, "/f1", "/f2");
+
+        assert_eq!(rerouted.len(), 1);
+        assert_eq!(rerouted[0].folder2_path, PathBuf::from("/f2/b"));
+        let first_into_b = moves.iter().position(|(_, to)| to == Path::new("/f2/b")).unwrap();
+        let b_leaves = moves.iter().position(|(from, _)| from == Path::new("/f2/b")).unwrap();
+        assert!(b_leaves < first_into_b);
+    }
+
+    #[test]
+    fn rename_script_quotes_paths_for_sh_and_cmd() {
+        let dir = TempDir::new("rename-script");
+        let pairs = [rename(RenameKind::Renamed, "/f1/50% it's done.txt", "/f2/draft.txt")];
+
+        let sh = dir.0.join("apply.sh");
+        write_rename_script(&pairs, "/f1", "/f2", &sh).unwrap();
+        let sh = std::fs::read_to_string(sh).unwrap();
+        assert!(sh.contains("mv -n '/f2/draft.txt' '/f2/50% it'\\''s done.txt'"), "{}", sh);
+
+        let bat = dir.0.join("apply.bat");
+        write_rename_script(&pairs, "/f1", "/f2", &bat).unwrap();
+        let bat = std::fs::read_to_string(bat).unwrap();
+        assert!(bat.contains("chcp 65001"), "{}", bat);
+        assert!(bat.contains("move \"/f2/draft.txt\" \"/f2/50%% it's done.txt\""), "{}", bat);
+    }
+
+    /// A minimal JPEG: SOI, APP1 with `exif`, DQT, SOS, `scan` data, EOI.
+    fn jpeg(exif: &[u8], scan: &[u8]) -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        data.extend(((exif.len() + 2) as u16).to_be_bytes());
+        data.extend(exif);
+        data.extend([0xFF, 0xDB, 0x00, 0x05, 0x00, 0x01, 0x02]);
+        data.extend([0xFF, 0xDA, 0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x3F, 0x00]);
+        data.extend(scan);
+        data.extend([0xFF, 0xD9]);
+        data
+    }
+
+    #[test]
+    fn jpeg_payload_ignores_app_segments_and_trailers() {
+        let dir = TempDir::new("jpeg");
+        let original = dir.write("a.jpg", &jpeg(b"Exif\0\0DateTime 2020", &[1, 2, 0xFF, 0x00, 3]));
+        let mut retagged = jpeg(b"Exif\0\0DateTime 2024:02:02 GPS 52.1N", &[1, 2, 0xFF, 0x00, 3]);
+        retagged.extend(b"maker trailer");
+        let retagged = dir.write("b.jpg", &retagged);
+        let edited = dir.write("c.jpg", &jpeg(b"Exif\0\0DateTime 2020", &[1, 2, 0xFF, 0x00, 4]));
+
+        let hash = |path: &Path| payload_hash(path).unwrap().unwrap();
+        assert_eq!(hash(&original).0, "JPEG");
+        assert_eq!(hash(&original), hash(&retagged));
+        assert_ne!(hash(&original), hash(&edited));
+    }
+
+    #[test]
+    fn jpeg_payload_rejects_truncated_files() {
+        let dir = TempDir::new("jpeg");
+        let mut data = jpeg(b"Exif", &[1, 2, 3]);
+        data.truncate(data.len() - 5);
+        assert_eq!(payload_hash(&dir.write("cut.jpg", &data)).unwrap(), None);
+    }
+
+    /// A PNG from (chunk type, data) pairs; CRCs are left zero.
+    fn png(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+        for (chunk_type, body) in chunks {
+            data.extend((body.len() as u32).to_be_bytes());
+            data.extend(*chunk_type);
+            data.extend(*body);
+            data.extend([0; 4]);
+        }
+        data
+    }
+
+    #[test]
+    fn png_payload_ignores_text_chunks_and_idat_splits() {
+        let dir = TempDir::new("png");
+        let header: &[u8] = &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0];
+        let original = dir.write("a.png", &png(&[
+            (b"IHDR", header),
+            (b"tEXt", b"Software\0camera 1.0"),
+            (b"IDAT", b"pixels"),
+            (b"IEND", b""),
+        ]));
+        let retagged = dir.write("b.png", &png(&[
+            (b"IHDR", header),
+            (b"tIME", b"\x07\xe8\x02\x02\0\0\0"),
+            (b"IDAT", b"pix"),
+            (b"IDAT", b"els"),
+            (b"iTXt", b"Comment\0\0\0\0\0edited"),
+            (b"IEND", b""),
+        ]));
+        let edited = dir.write("c.png", &png(&[(b"IHDR", header), (b"IDAT", b"pixelz"), (b"IEND", b"")]));
+
+        let hash = |path: &Path| payload_hash(path).unwrap().unwrap();
+        assert_eq!(hash(&original).0, "PNG");
+        assert_eq!(hash(&original), hash(&retagged));
+        assert_ne!(hash(&original), hash(&edited));
+    }
+
+    #[test]
+    fn id3v2_tags_are_skipped_including_footers() {
+        let dir = TempDir::new("id3");
+        // Syncsafe size 200 = 0b1_1001000 -> [0, 0, 1, 0x48]
+        let mut data = b"ID3\x03\x00\x00\x00\x00\x01\x48".to_vec();
+        data.extend([0; 200]);
+        data.extend(b"ID3\x04\x00\x10\x00\x00\x00\x05");
+        data.extend([0; 5 + 10]);
+        data.extend(b"fLaC");
+        let path = dir.write("tagged.flac", &data);
+
+        assert_eq!(id3v2_tag_len(&mut File::open(&path).unwrap()).unwrap(), 210 + 25);
+        let untagged = dir.write("plain.flac", b"fLaC");
+        assert_eq!(id3v2_tag_len(&mut File::open(&untagged).unwrap()).unwrap(), 0);
+    }
+
+    fn pdf(info: &str, length: &str) -> Vec<u8> {
+        format!(
+            "%PDF-1.4\n1 0 obj\n<< /Type /Catalog >>\nendobj\n\
+             2 0 obj\n<< /Length {} >>\nstream\nBT (Hello) Tj ET\nendstream\nendobj\n\
+             3 0 obj\n<< /Producer ({}) >>\nendobj\n\
+             trailer\n<< /Root 1 0 R /Info 3 0 R >>\n%%EOF\n",
+            length, info
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn pdf_payload_ignores_the_info_dictionary() {
+        let dir = TempDir::new("pdf");
+        let original = dir.write("a.pdf", &pdf("writer 1", "17"));
+        let resaved = dir.write("b.pdf", &pdf("writer 2, saved 2024-02-02", "17"));
+
+        let hash = |path: &Path| payload_hash(path).unwrap().unwrap();
+        assert_eq!(hash(&original).0, "PDF");
+        assert_eq!(hash(&original), hash(&resaved));
+    }
+
+    #[test]
+    fn pdf_huge_length_does_not_overflow() {
+        let dir = TempDir::new("pdf");
+        let path = dir.write("crafted.pdf", &pdf("x", "18446744073709551615"));
+        assert!(payload_hash(&path).is_ok());
+    }
+
+    #[test]
+    fn cdc_shared_bytes_counts_common_chunks_once_per_match() {
+        let a = [(1, 100), (2, 50), (2, 50), (5, 7)];
+        let b = [(2, 50), (3, 10), (5, 7)];
+        assert_eq!(cdc_shared_bytes(&a, &b), 57);
+        assert_eq!(cdc_shared_bytes(&a, &a), 207);
+        assert_eq!(cdc_shared_bytes(&a, &[]), 0);
+    }
+
+    #[test]
+    fn cdc_fingerprints_survive_an_inserted_byte() {
+        let dir = TempDir::new("cdc");
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let data: Vec<u8> = (0..200_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let mut shifted = data.clone();
+        shifted.insert(1000, b'!');
+        let a = cdc_fingerprint(&dir.write("a.bin", &data)).unwrap();
+        let b = cdc_fingerprint(&dir.write("b.bin", &shifted)).unwrap();
+
+        // Only the chunk around the edit differs
+        let shared = cdc_shared_bytes(&a, &b);
+        assert!(shared >= data.len() as u64 - CDC_MAX_CHUNK as u64, "{} shared", shared);
+        assert_eq!(a.iter().map(|c| c.1 as u64).sum::<u64>(), data.len() as u64);
+    }
+
+    /// Symmetric distance lookup over a fixed table; None = over the threshold.
+    fn table_distance(table: &[((usize, usize), Option<u64>)]) -> impl FnMut(usize, usize) -> Option<u64> + '_ {
+        move |a, b| {
+            table
+                .iter()
+                .find(|((x, y), _)| (*x, *y) == (a.min(b), a.max(b)))
+                .and_then(|(_, d)| *d)
+        }
+    }
+
+    #[test]
+    fn clusters_reject_chains_that_break_the_diameter() {
+        // 0≈1 and 1≈2, but 0 and 2 are too far apart to share a cluster
+        let table = [((0, 1), Some(1)), ((1, 2), Some(2)), ((0, 2), None)];
+        let edges = vec![(1, 0, 1), (2, 1, 2)];
+        let clusters = cluster_near_duplicates(edges, table_distance(&table));
+
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0].members, vec![0, 1]);
+        assert_eq!((clusters[0].diameter, clusters[0].widest), (1, (0, 1)));
+    }
+
+    #[test]
+    fn clusters_merge_when_every_pair_is_close() {
+        let table = [((0, 1), Some(1)), ((1, 2), Some(2)), ((0, 2), Some(3)), ((3, 4), Some(1))];
+        let edges = vec![(2, 1, 2), (1, 0, 1), (1, 3, 4)];
+        let clusters = cluster_near_duplicates(edges, table_distance(&table));
+
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].members, vec![0, 1, 2]);
+        assert_eq!((clusters[0].diameter, clusters[0].widest), (3, (0, 2)));
+        assert_eq!(clusters[1].members, vec![3, 4]);
+    }
+
+    #[test]
+    fn fuzzy_move_keeps_one_file_of_a_single_folder_cluster() {
+        let dir = TempDir::new("fuzzy-move");
+        for name in ["b.jpg", "a.jpg", "c.jpg"] {
+            dir.write(name, name.as_bytes());
+        }
+        let folder1 = dir.0.to_str().unwrap();
+        let scan: HashMap<PathBuf, FileInfo> = collect_files(folder1, 0, true, false)
+            .unwrap()
+            .into_iter()
+            .map(|f| (f.path.clone(), f))
+            .collect();
+        let group = DuplicateGroup {
+            files_by_folder: vec![["b.jpg", "a.jpg", "c.jpg"].iter().map(|n| dir.0.join(n)).collect(), Vec::new()],
+            size: 5,
+            match_kind: MatchKind::Fuzzy { bytes_differing: 1, reason: None },
+        };
+        let mut options = test_options();
+        options.force_delete = true;
+        options.intra_folder = true;
+        move_fuzzy_to_differ(&[group], folder1, "", &scan, &options).unwrap();
+
+        assert!(dir.0.join("a.jpg").is_file());
+        assert!(dir.0.join("differ/b.jpg").is_file());
+        assert!(dir.0.join("differ/c.jpg").is_file());
+    }
+
+    #[test]
+    fn text_normalization_ignores_line_endings_bom_and_trailing_blanks() {
+        let dir = TempDir::new("text");
+        let hash = |name: &str, content: &[u8]| text_normalized_hash(&dir.write(name, content)).unwrap();
+
+        let unix = hash("unix.txt", b"fn main() {\n    run();\n}\n");
+        assert!(unix.is_some());
+        assert_eq!(hash("windows.txt", b"\xEF\xBB\xBFfn main() {\r\n    run();  \r\n}\r\n\r\n"), unix);
+        assert_eq!(hash("mac.txt", b"fn main() {\r    run();\t\r}"), unix);
+
+        // Leading and inner whitespace still count, and so do blank lines inside
+        assert_ne!(hash("indent.txt", b"fn main() {\n  run();\n}\n"), unix);
+        assert_ne!(hash("blank.txt", b"fn main() {\n\n    run();\n}\n"), unix);
+    }
+
+    #[test]
+    fn text_normalization_skips_binary_files() {
+        let dir = TempDir::new("text");
+        assert_eq!(text_normalized_hash(&dir.write("nul.bin", b"abc\0def")).unwrap(), None);
+        assert_eq!(text_normalized_hash(&dir.write("latin1.txt", b"caf\xE9 au lait")).unwrap(), None);
+    }
+
+    fn sampling(sample_size: u64, samples: u64) -> QuickSampling {
+        QuickSampling { sample_size, samples, random_seed: None, adaptive: false }
+    }
+
+    #[test]
+    fn sample_offsets_hash_small_files_in_full() {
+        let head_tail = sampling(10, 2);
+        assert_eq!(head_tail.offsets(0), None);
+        assert_eq!(head_tail.offsets(20), None);
+        assert_eq!(head_tail.match_kind(20), MatchKind::Exact);
+        assert_eq!(head_tail.offsets(21), Some(vec![0, 11]));
+        assert_eq!(head_tail.match_kind(21), MatchKind::QuickMatch);
+
+        assert_eq!(sampling(10, 1).offsets(11), Some(vec![0]));
+        assert_eq!(sampling(10, 3).offsets(31), Some(vec![0, 10, 21]));
+    }
+
+    #[test]
+    fn adaptive_sampling_adds_samples_for_big_files() {
+        let mut adaptive = sampling(1, 2);
+        adaptive.adaptive = true;
+        assert_eq!(adaptive.offsets(QUICKCHECK_ADAPTIVE_SPAN).unwrap().len(), 2);
+
+        let size = 3 * QUICKCHECK_ADAPTIVE_SPAN;
+        assert_eq!(adaptive.offsets(size), Some(vec![0, (size - 1) / 2, size - 1]));
+
+        let huge = adaptive.offsets(1000 * QUICKCHECK_ADAPTIVE_SPAN).unwrap();
+        assert_eq!(huge.len() as u64, QUICKCHECK_MAX_SAMPLES);
+
+        // Adaptive sample count also decides when a file is read in full
+        adaptive.sample_size = QUICKCHECK_ADAPTIVE_SPAN;
+        assert_eq!(adaptive.offsets(size), None);
+    }
+
+    #[test]
+    fn random_sample_offsets_stay_in_their_slots() {
+        let mut random = sampling(100, 4);
+        random.random_seed = Some(42);
+        let size = 10_000;
+        let offsets = random.offsets(size).unwrap();
+
+        assert_eq!(offsets, random.offsets(size).unwrap());
+        for (i, offset) in offsets.iter().enumerate() {
+            let slot = i as u64 * size / 4;
+            assert!(*offset >= slot && offset + 100 <= slot + size / 4, "{:?}", offsets);
+        }
+        random.random_seed = Some(43);
+        assert_ne!(random.offsets(size).unwrap(), offsets);
+    }
+
+    #[test]
+    fn byte_sizes_parse_binary_suffixes() {
+        assert_eq!(parse_byte_size("4096"), Ok(4096));
+        assert_eq!(parse_byte_size("512K"), Ok(512 << 10));
+        assert_eq!(parse_byte_size("8m"), Ok(8 << 20));
+        assert_eq!(parse_byte_size("8MB"), Ok(8 << 20));
+        assert_eq!(parse_byte_size(" 1GiB "), Ok(1 << 30));
+    }
+
+    #[test]
+    fn byte_sizes_reject_zero_junk_and_overflow() {
+        assert!(parse_byte_size("0").is_err());
+        assert!(parse_byte_size("").is_err());
+        assert!(parse_byte_size("M").is_err());
+        assert!(parse_byte_size("-1K").is_err());
+        assert!(parse_byte_size("1T").is_err());
+        assert_eq!(
+            parse_byte_size(&format!("{}K", u64::MAX)),
+            Err(format!("size too large: {}K", u64::MAX))
+        );
+        assert_eq!(parse_byte_size(&format!("{}", u64::MAX)), Ok(u64::MAX));
+    }
+
+    #[test]
+    fn quick_size_default_matches_the_constant() {
+        assert_eq!(parse_byte_size(quick_size_default()), Ok(QUICKCHECK_SIZE as u64));
+    }
+}